@@ -0,0 +1,44 @@
+//! The typed chunk data parsers. The first byte picks the parser and the rest is the
+//! chunk data, which must serialize back unchanged when accepted.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use png_rs::apng::{AnimationControl, FrameControl};
+use png_rs::ihdr::ImageHeader;
+use png_rs::payload::PartHeader;
+use png_rs::signature::Signature;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&parser, data)) = data.split_first() else {
+        return;
+    };
+
+    match parser % 5 {
+        0 => {
+            if let Ok(header) = ImageHeader::from_bytes(data) {
+                assert_eq!(header.to_bytes(), data);
+            }
+        }
+        1 => {
+            if let Ok(control) = AnimationControl::from_bytes(data) {
+                assert_eq!(control.to_bytes(), data);
+            }
+        }
+        2 => {
+            if let Ok(control) = FrameControl::from_bytes(data) {
+                assert_eq!(control.to_bytes(), data);
+            }
+        }
+        3 => {
+            if let Ok(signature) = Signature::from_bytes(data) {
+                assert_eq!(signature.to_bytes(), data);
+                let _ = signature.verify(&[]);
+            }
+        }
+        _ => {
+            if let Ok((header, rest)) = PartHeader::from_bytes(data) {
+                assert_eq!([header.to_bytes(), rest.to_vec()].concat(), data);
+            }
+        }
+    }
+});
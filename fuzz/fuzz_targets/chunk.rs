@@ -0,0 +1,11 @@
+//! `Chunk::try_from` on arbitrary bytes. Accepted chunks must serialize back to the input.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use png_rs::chunk::Chunk;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(chunk) = Chunk::try_from(data) {
+        assert_eq!(chunk.as_bytes(), data);
+    }
+});
@@ -0,0 +1,31 @@
+//! Full image decoding, animated PNGs included. Decoded pixels must survive a
+//! re-encode.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use png_rs::limits::DecodeLimits;
+use png_rs::png::Png;
+
+/// Tight enough that no input gets near libFuzzer's default memory limit.
+fn limits() -> DecodeLimits {
+    DecodeLimits {
+        max_decompressed_size: 16 << 20,
+        max_pixels: 1 << 20,
+        ..DecodeLimits::default()
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(png) = Png::from_bytes_with_limits(data, limits()) else {
+        return;
+    };
+
+    if let Ok(image) = png.decode_rgba() {
+        assert!(png.pixel_hash().is_ok());
+
+        let mut reencoded = image.to_png();
+        reencoded.set_limits(limits());
+        assert_eq!(reencoded.decode_rgba().unwrap(), image);
+    }
+    let _ = png.render_frames();
+});
@@ -0,0 +1,28 @@
+//! The IDAT path on its own: 13 IHDR bytes followed by a zlib stream. Decoded
+//! scanlines must survive a re-encode.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use png_rs::ihdr::ImageHeader;
+use png_rs::image;
+use png_rs::limits::DecodeLimits;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < ImageHeader::LENGTH {
+        return;
+    }
+    let (header, compressed) = data.split_at(ImageHeader::LENGTH);
+    let Ok(header) = ImageHeader::from_bytes(header) else {
+        return;
+    };
+    let limits = DecodeLimits {
+        max_decompressed_size: 16 << 20,
+        max_pixels: 1 << 20,
+        ..DecodeLimits::default()
+    };
+
+    if let Ok(raw) = image::decode_pixels(&header, compressed, &limits) {
+        let reencoded = image::encode_pixels(&header, &raw).unwrap();
+        assert_eq!(image::decode_pixels(&header, &reencoded, &limits).unwrap(), raw);
+    }
+});
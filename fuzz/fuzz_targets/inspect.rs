@@ -0,0 +1,21 @@
+//! The `inspect` report on arbitrary bytes. It must never panic or allocate what the
+//! header claims, whatever state the file is in.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use png_rs::inspect;
+use png_rs::limits::DecodeLimits;
+
+/// Tight enough that no input gets near libFuzzer's default memory limit.
+fn limits() -> DecodeLimits {
+    DecodeLimits {
+        max_decompressed_size: 16 << 20,
+        max_pixels: 1 << 20,
+        ..DecodeLimits::default()
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let report = inspect::inspect_with_limits(data, limits());
+    assert!(report.score() <= 100);
+});
@@ -0,0 +1,21 @@
+//! `Png::try_from` and the lenient recovery parser on arbitrary bytes. Accepted files
+//! must serialize back to the input, and the lenient parser must agree with the strict
+//! one on them.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use png_rs::limits::DecodeLimits;
+use png_rs::png::Png;
+use png_rs::{recover, registry};
+
+fuzz_target!(|data: &[u8]| {
+    let recovered = recover::parse_lenient(data, DecodeLimits::default());
+
+    if let Ok(png) = Png::try_from(data) {
+        assert_eq!(png.as_bytes(), data);
+        assert!(recovered.problems.is_empty(), "{:?}", recovered.problems);
+        assert_eq!(recovered.png.as_bytes(), data);
+
+        registry::validate(png.chunks());
+    }
+});
@@ -0,0 +1,22 @@
+//! The zlib helpers on arbitrary streams. The strict and partial decoders must agree,
+//! and inflated data must survive a compress round trip.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use png_rs::zlib;
+
+const LIMIT: usize = 16 << 20;
+
+fuzz_target!(|data: &[u8]| {
+    let (partial, error) = zlib::decompress_partial(data, LIMIT);
+
+    match zlib::decompress_prefix(data, LIMIT) {
+        Ok((output, used)) => {
+            assert!(used <= data.len());
+            assert!(error.is_none());
+            assert_eq!(partial, output);
+            assert_eq!(zlib::decompress(&zlib::compress(&output), LIMIT).unwrap(), output);
+        }
+        Err(_) => assert!(error.is_some()),
+    }
+});
@@ -0,0 +1,828 @@
+use crate::chunk::{Chunk, ChunkView};
+use crate::chunk_type::ChunkType;
+use crate::ihdr::ImageHeader;
+use crate::image::{self, RgbaImage};
+use crate::limits::DecodeLimits;
+use crate::png::Png;
+
+use std::fmt;
+use std::str::FromStr;
+
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+fn be_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+/// The contents of an `acTL` chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationControl {
+    pub num_frames: u32,
+    /// Number of times to loop the animation, 0 means forever.
+    pub num_plays: u32,
+}
+
+impl AnimationControl {
+    pub const LENGTH: usize = 8;
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.len() != Self::LENGTH {
+            return Err(format!(
+                "acTL must be {} bytes, found {}",
+                Self::LENGTH,
+                data.len()
+            ));
+        }
+
+        let num_frames = be_u32(data, 0);
+        if num_frames == 0 {
+            return Err("acTL declares zero frames".to_string());
+        }
+
+        Ok(AnimationControl {
+            num_frames,
+            num_plays: be_u32(data, 4),
+        })
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        [self.num_frames.to_be_bytes(), self.num_plays.to_be_bytes()].concat()
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        Chunk::new(ChunkType::from_str("acTL").unwrap(), self.to_bytes())
+    }
+}
+
+/// How the frame area is disposed of before rendering the next frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposeOp {
+    None = 0,
+    Background = 1,
+    Previous = 2,
+}
+
+/// How the frame is combined with the output buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendOp {
+    Source = 0,
+    Over = 1,
+}
+
+/// The contents of an `fcTL` chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameControl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+impl FrameControl {
+    pub const LENGTH: usize = 26;
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.len() != Self::LENGTH {
+            return Err(format!(
+                "fcTL must be {} bytes, found {}",
+                Self::LENGTH,
+                data.len()
+            ));
+        }
+
+        let dispose_op = match data[24] {
+            0 => DisposeOp::None,
+            1 => DisposeOp::Background,
+            2 => DisposeOp::Previous,
+            op => return Err(format!("invalid fcTL dispose op {}", op)),
+        };
+        let blend_op = match data[25] {
+            0 => BlendOp::Source,
+            1 => BlendOp::Over,
+            op => return Err(format!("invalid fcTL blend op {}", op)),
+        };
+
+        Ok(FrameControl {
+            sequence_number: be_u32(data, 0),
+            width: be_u32(data, 4),
+            height: be_u32(data, 8),
+            x_offset: be_u32(data, 12),
+            y_offset: be_u32(data, 16),
+            delay_num: be_u16(data, 20),
+            delay_den: be_u16(data, 22),
+            dispose_op,
+            blend_op,
+        })
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::LENGTH);
+        bytes.extend_from_slice(&self.sequence_number.to_be_bytes());
+        bytes.extend_from_slice(&self.width.to_be_bytes());
+        bytes.extend_from_slice(&self.height.to_be_bytes());
+        bytes.extend_from_slice(&self.x_offset.to_be_bytes());
+        bytes.extend_from_slice(&self.y_offset.to_be_bytes());
+        bytes.extend_from_slice(&self.delay_num.to_be_bytes());
+        bytes.extend_from_slice(&self.delay_den.to_be_bytes());
+        bytes.push(self.dispose_op as u8);
+        bytes.push(self.blend_op as u8);
+        bytes
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        Chunk::new(ChunkType::from_str("fcTL").unwrap(), self.to_bytes())
+    }
+
+    /// The frame delay in seconds. A zero denominator means 1/100ths of a second.
+    pub fn delay_secs(&self) -> f64 {
+        let den = if self.delay_den == 0 {
+            100
+        } else {
+            self.delay_den
+        };
+        self.delay_num as f64 / den as f64
+    }
+
+    /// Checks that the frame region is non-empty and fits inside the canvas.
+    fn check_region(&self, header: &ImageHeader) -> Result<(), String> {
+        let fits = |offset: u32, size: u32, canvas: u32| {
+            size > 0 && offset.checked_add(size).is_some_and(|end| end <= canvas)
+        };
+        if !fits(self.x_offset, self.width, header.width)
+            || !fits(self.y_offset, self.height, header.height)
+        {
+            return Err(format!(
+                "frame {} region {}x{}+{}+{} does not fit the {}x{} canvas",
+                self.sequence_number,
+                self.width,
+                self.height,
+                self.x_offset,
+                self.y_offset,
+                header.width,
+                header.height
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A single frame of an animated PNG.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub control: FrameControl,
+    /// The frame's compressed zlib stream, concatenated from its IDAT chunks or
+    /// from its fdAT chunks with the sequence numbers removed.
+    pub data: Vec<u8>,
+    /// True if this frame is stored in IDAT and doubles as the static image.
+    pub is_default_image: bool,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{} at ({}, {}) delay:{:.3}s dispose:{:?} blend:{:?} data_len:{}{}",
+            self.control.width,
+            self.control.height,
+            self.control.x_offset,
+            self.control.y_offset,
+            self.control.delay_secs(),
+            self.control.dispose_op,
+            self.control.blend_op,
+            self.data.len(),
+            if self.is_default_image {
+                " (default image)"
+            } else {
+                ""
+            }
+        )
+    }
+}
+
+/// Splits the chunks of an animated PNG into frames, checking that fcTL and fdAT
+/// sequence numbers start at 0 and increase without gaps.
+pub fn frames<C: ChunkView>(header: &ImageHeader, chunks: &[C]) -> Result<Vec<Frame>, String> {
+    let control = chunks
+        .iter()
+        .find(|chunk| chunk.chunk_type().bytes() == *b"acTL")
+        .ok_or("not an animated PNG, missing acTL chunk")?;
+    let control = AnimationControl::from_bytes(control.data())?;
+
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut next_sequence_number = 0;
+    let mut seen_idat = false;
+
+    let mut check_sequence = |sequence_number: u32, index: usize| {
+        if sequence_number != next_sequence_number {
+            return Err(format!(
+                "expected sequence number {}, found {} at chunk index {}",
+                next_sequence_number, sequence_number, index
+            ));
+        }
+        next_sequence_number += 1;
+        Ok(())
+    };
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        match &chunk.chunk_type().bytes() {
+            b"fcTL" => {
+                let frame_control = FrameControl::from_bytes(chunk.data())?;
+                check_sequence(frame_control.sequence_number, index)?;
+                frame_control.check_region(header)?;
+
+                let is_default_image = !seen_idat;
+                if is_default_image
+                    && (
+                        frame_control.width,
+                        frame_control.height,
+                        frame_control.x_offset,
+                        frame_control.y_offset,
+                    ) != (header.width, header.height, 0, 0)
+                {
+                    return Err("the default image frame must cover the whole canvas".to_string());
+                }
+
+                frames.push(Frame {
+                    control: frame_control,
+                    data: Vec::new(),
+                    is_default_image,
+                });
+            }
+            b"IDAT" => {
+                seen_idat = true;
+                if let Some(frame) = frames.last_mut().filter(|frame| frame.is_default_image) {
+                    frame.data.extend_from_slice(chunk.data());
+                }
+            }
+            b"fdAT" => {
+                if chunk.data().len() < 4 {
+                    return Err(format!("fdAT at chunk index {} is too short", index));
+                }
+                check_sequence(be_u32(chunk.data(), 0), index)?;
+
+                match frames.last_mut() {
+                    Some(frame) if !frame.is_default_image => {
+                        frame.data.extend_from_slice(&chunk.data()[4..])
+                    }
+                    _ => {
+                        return Err(format!(
+                            "fdAT at chunk index {} does not follow an fcTL",
+                            index
+                        ))
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if frames.len() != control.num_frames as usize {
+        return Err(format!(
+            "acTL declares {} frames but {} were found",
+            control.num_frames,
+            frames.len()
+        ));
+    }
+    if let Some(frame) = frames.iter().find(|frame| frame.data.is_empty()) {
+        return Err(format!(
+            "frame {} has no image data",
+            frame.control.sequence_number
+        ));
+    }
+
+    Ok(frames)
+}
+
+/// Composites non-premultiplied `source` over `destination`.
+fn blend_over(destination: [u8; 4], source: [u8; 4]) -> [u8; 4] {
+    let source_alpha = source[3] as u32;
+    if source_alpha == 255 {
+        return source;
+    }
+    if source_alpha == 0 {
+        return destination;
+    }
+
+    let destination_alpha = destination[3] as u32 * (255 - source_alpha) / 255;
+    let alpha = source_alpha + destination_alpha;
+    let channel = |index: usize| {
+        ((source[index] as u32 * source_alpha + destination[index] as u32 * destination_alpha)
+            / alpha) as u8
+    };
+    [channel(0), channel(1), channel(2), alpha as u8]
+}
+
+/// Renders `frames` onto a canvas the size of `header`, applying each frame's blend op
+/// and then its dispose op before the next frame. Returns a full canvas per frame, so
+/// the canvases together must fit in `limits.max_decompressed_size`.
+pub fn render(
+    header: &ImageHeader,
+    frames: &[Frame],
+    palette: Option<&[u8]>,
+    transparency: Option<&[u8]>,
+    limits: &DecodeLimits,
+) -> Result<Vec<RgbaImage>, String> {
+    limits.check_dimensions(header)?;
+    let canvas_size = header.width as u64 * header.height as u64 * 4;
+    if canvas_size.saturating_mul(frames.len() as u64) > limits.max_decompressed_size as u64 {
+        return Err(format!(
+            "{} frames of {}x{} pixels exceed the limit of {} bytes",
+            frames.len(),
+            header.width,
+            header.height,
+            limits.max_decompressed_size
+        ));
+    }
+
+    let mut canvas = RgbaImage::new(header.width, header.height);
+    let mut rendered = Vec::with_capacity(frames.len());
+
+    for (index, frame) in frames.iter().enumerate() {
+        let control = &frame.control;
+        let frame_header = ImageHeader {
+            width: control.width,
+            height: control.height,
+            ..*header
+        };
+        let raw = image::decode_pixels(&frame_header, &frame.data, limits)
+            .map_err(|e| format!("frame {}: {}", index, e))?;
+        let pixels = RgbaImage::from_raw(&frame_header, &raw, palette, transparency)?;
+
+        let region = (0..control.height).flat_map(|y| (0..control.width).map(move |x| (x, y)));
+        let to_canvas = |x: u32, y: u32| (x + control.x_offset, y + control.y_offset);
+
+        // The first frame treats "previous" like "background", as there is nothing to restore.
+        let dispose_op = match control.dispose_op {
+            DisposeOp::Previous if index == 0 => DisposeOp::Background,
+            op => op,
+        };
+        let previous = (dispose_op == DisposeOp::Previous).then(|| canvas.clone());
+
+        for (x, y) in region.clone() {
+            let (canvas_x, canvas_y) = to_canvas(x, y);
+            let source = pixels.pixel(x, y);
+            let pixel = match control.blend_op {
+                BlendOp::Source => source,
+                BlendOp::Over => blend_over(canvas.pixel(canvas_x, canvas_y), source),
+            };
+            canvas.set_pixel(canvas_x, canvas_y, pixel);
+        }
+        rendered.push(canvas.clone());
+
+        match dispose_op {
+            DisposeOp::None => {}
+            DisposeOp::Background => {
+                for (x, y) in region {
+                    let (canvas_x, canvas_y) = to_canvas(x, y);
+                    canvas.set_pixel(canvas_x, canvas_y, [0; 4]);
+                }
+            }
+            DisposeOp::Previous => canvas = previous.expect("saved before drawing"),
+        }
+    }
+
+    Ok(rendered)
+}
+
+/// One input frame for `assemble`.
+#[derive(Debug, Clone)]
+pub struct AnimationFrame {
+    pub image: RgbaImage,
+    pub delay_num: u16,
+    pub delay_den: u16,
+}
+
+/// Options controlling how `assemble` lays out an animated PNG.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AssembleOptions {
+    /// Number of times to play the animation, 0 loops forever.
+    pub num_plays: u32,
+    /// Store the first frame only as the static image shown by decoders without APNG
+    /// support, and leave it out of the animation.
+    pub hidden_default_image: bool,
+    /// Store only the rectangle that changed since the previous frame.
+    pub crop: bool,
+}
+
+/// Smallest rectangle (x, y, width, height) containing every pixel that differs
+/// between `previous` and `current`, or a single pixel if they are identical.
+fn changed_region(previous: &RgbaImage, current: &RgbaImage) -> (u32, u32, u32, u32) {
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for y in 0..current.height {
+        for x in 0..current.width {
+            if previous.pixel(x, y) != current.pixel(x, y) {
+                bounds = Some(match bounds {
+                    None => (x, y, x, y),
+                    Some((min_x, min_y, max_x, max_y)) => {
+                        (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))
+                    }
+                });
+            }
+        }
+    }
+
+    match bounds {
+        Some((min_x, min_y, max_x, max_y)) => (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1),
+        None => (0, 0, 1, 1),
+    }
+}
+
+/// Builds an 8-bit RGBA animated PNG from `frames`, which must all have the same dimensions.
+pub fn assemble(frames: &[AnimationFrame], options: AssembleOptions) -> Result<Png, String> {
+    let first = frames
+        .first()
+        .ok_or("an animation needs at least one frame")?;
+    let (width, height) = (first.image.width, first.image.height);
+    if let Some((index, frame)) = frames
+        .iter()
+        .enumerate()
+        .find(|(_, frame)| (frame.image.width, frame.image.height) != (width, height))
+    {
+        return Err(format!(
+            "frame {} is {}x{}, expected {}x{}",
+            index, frame.image.width, frame.image.height, width, height
+        ));
+    }
+
+    let animated = if options.hidden_default_image {
+        &frames[1..]
+    } else {
+        frames
+    };
+    if animated.is_empty() {
+        return Err("a hidden default image needs at least one more frame".to_string());
+    }
+
+    let header = first.image.header();
+    let mut chunks = vec![
+        header.to_chunk(),
+        AnimationControl {
+            num_frames: animated.len() as u32,
+            num_plays: options.num_plays,
+        }
+        .to_chunk(),
+    ];
+    let mut sequence_number = 0;
+
+    if options.hidden_default_image {
+        chunks.push(Chunk::new(
+            ChunkType::from_str("IDAT").unwrap(),
+            image::encode_pixels(&header, &first.image.pixels)?,
+        ));
+    }
+
+    let mut has_idat = options.hidden_default_image;
+    let mut previous: Option<&RgbaImage> = None;
+    for frame in animated {
+        let (x_offset, y_offset, frame_width, frame_height) = match previous {
+            Some(previous) if options.crop => changed_region(previous, &frame.image),
+            _ => (0, 0, width, height),
+        };
+        let pixels = frame
+            .image
+            .crop(x_offset, y_offset, frame_width, frame_height);
+
+        chunks.push(
+            FrameControl {
+                sequence_number,
+                width: frame_width,
+                height: frame_height,
+                x_offset,
+                y_offset,
+                delay_num: frame.delay_num,
+                delay_den: frame.delay_den,
+                dispose_op: DisposeOp::None,
+                blend_op: BlendOp::Source,
+            }
+            .to_chunk(),
+        );
+        sequence_number += 1;
+
+        let data = image::encode_pixels(&pixels.header(), &pixels.pixels)?;
+        if !has_idat {
+            chunks.push(Chunk::new(ChunkType::from_str("IDAT").unwrap(), data));
+            has_idat = true;
+        } else {
+            let data = [&sequence_number.to_be_bytes()[..], &data].concat();
+            chunks.push(Chunk::new(ChunkType::from_str("fdAT").unwrap(), data));
+            sequence_number += 1;
+        }
+
+        previous = Some(&frame.image);
+    }
+
+    chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+    Ok(Png::from_chunks(chunks))
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::ColorType;
+
+    fn testing_header() -> ImageHeader {
+        ImageHeader {
+            width: 4,
+            height: 4,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        }
+    }
+
+    fn frame_control(sequence_number: u32, width: u32, height: u32) -> FrameControl {
+        FrameControl {
+            sequence_number,
+            width,
+            height,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        }
+    }
+
+    fn chunk(name: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(name).unwrap(), data.to_vec())
+    }
+
+    fn fdat(sequence_number: u32, data: &[u8]) -> Chunk {
+        chunk("fdAT", &[&sequence_number.to_be_bytes()[..], data].concat())
+    }
+
+    fn testing_chunks() -> Vec<Chunk> {
+        let control = AnimationControl {
+            num_frames: 2,
+            num_plays: 0,
+        };
+        vec![
+            testing_header().to_chunk(),
+            control.to_chunk(),
+            frame_control(0, 4, 4).to_chunk(),
+            chunk("IDAT", b"first"),
+            chunk("IDAT", b"frame"),
+            frame_control(1, 2, 2).to_chunk(),
+            fdat(2, b"second"),
+            fdat(3, b"frame"),
+            chunk("IEND", b""),
+        ]
+    }
+
+    fn solid_frame(control: FrameControl, rgba: [u8; 4]) -> Frame {
+        let mut pixels = RgbaImage::new(control.width, control.height);
+        for y in 0..control.height {
+            for x in 0..control.width {
+                pixels.set_pixel(x, y, rgba);
+            }
+        }
+        Frame {
+            control,
+            data: image::encode_pixels(&pixels.header(), &pixels.pixels).unwrap(),
+            is_default_image: false,
+        }
+    }
+
+    #[test]
+    fn test_frame_control_round_trip() {
+        let mut control = frame_control(7, 3, 2);
+        control.dispose_op = DisposeOp::Previous;
+        control.blend_op = BlendOp::Over;
+
+        assert_eq!(
+            FrameControl::from_bytes(&control.to_bytes()).unwrap(),
+            control
+        );
+        assert_eq!(control.delay_secs(), 0.1);
+    }
+
+    #[test]
+    fn test_animation_control_rejects_zero_frames() {
+        assert!(AnimationControl::from_bytes(&[0; 8]).is_err());
+        assert!(AnimationControl::from_bytes(&[0, 0, 0, 1]).is_err());
+    }
+
+    #[test]
+    fn test_frames() {
+        let frames = frames(&testing_header(), &testing_chunks()).unwrap();
+
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].is_default_image);
+        assert_eq!(frames[0].data, b"firstframe");
+        assert!(!frames[1].is_default_image);
+        assert_eq!(frames[1].control.width, 2);
+        assert_eq!(frames[1].data, b"secondframe");
+    }
+
+    #[test]
+    fn test_frames_hidden_default_image() {
+        let control = AnimationControl {
+            num_frames: 2,
+            num_plays: 0,
+        };
+        let chunks = vec![
+            testing_header().to_chunk(),
+            control.to_chunk(),
+            chunk("IDAT", b"static"),
+            frame_control(0, 4, 4).to_chunk(),
+            fdat(1, b"zero"),
+            frame_control(2, 2, 2).to_chunk(),
+            fdat(3, b"one"),
+            chunk("IEND", b""),
+        ];
+
+        let frames = frames(&testing_header(), &chunks).unwrap();
+
+        assert!(frames.iter().all(|frame| !frame.is_default_image));
+        assert_eq!(frames[0].data, b"zero");
+        assert_eq!(frames[1].data, b"one");
+    }
+
+    #[test]
+    fn test_frames_rejects_sequence_gap() {
+        let mut chunks = testing_chunks();
+        chunks[7] = fdat(4, b"frame");
+
+        let error = frames(&testing_header(), &chunks).unwrap_err();
+        assert!(error.contains("expected sequence number 3, found 4"));
+    }
+
+    #[test]
+    fn test_frames_rejects_out_of_bounds_region() {
+        let mut chunks = testing_chunks();
+        let mut control = frame_control(1, 2, 2);
+        control.x_offset = 3;
+        chunks[5] = control.to_chunk();
+
+        assert!(frames(&testing_header(), &chunks).is_err());
+    }
+
+    #[test]
+    fn test_frames_rejects_frame_count_mismatch() {
+        let mut chunks = testing_chunks();
+        chunks.truncate(5);
+        chunks.push(chunk("IEND", b""));
+
+        assert!(frames(&testing_header(), &chunks).is_err());
+    }
+
+    #[test]
+    fn test_render_dispose_and_blend() {
+        let header = ImageHeader {
+            width: 2,
+            height: 2,
+            ..testing_header()
+        };
+        let mut first = frame_control(0, 2, 2);
+        first.dispose_op = DisposeOp::Background;
+        let mut second = frame_control(1, 1, 1);
+        second.x_offset = 1;
+        second.y_offset = 1;
+        second.blend_op = BlendOp::Over;
+        let mut third = frame_control(2, 1, 1);
+        third.dispose_op = DisposeOp::Previous;
+        let fourth = frame_control(3, 1, 1);
+
+        let frames = [
+            solid_frame(first, [255, 0, 0, 255]),
+            solid_frame(second, [0, 0, 255, 128]),
+            solid_frame(third, [0, 255, 0, 255]),
+            solid_frame(fourth, [0, 0, 0, 0]),
+        ];
+
+        let rendered = render(&header, &frames, None, None, &DecodeLimits::default()).unwrap();
+
+        assert_eq!(rendered.len(), 4);
+        assert!(rendered[0]
+            .pixels
+            .chunks(4)
+            .all(|pixel| pixel == [255, 0, 0, 255]));
+        // The red background was disposed, so the blue pixel blends over transparency.
+        assert_eq!(rendered[1].pixel(1, 1), [0, 0, 255, 128]);
+        assert_eq!(rendered[1].pixel(0, 0), [0, 0, 0, 0]);
+        assert_eq!(rendered[2].pixel(0, 0), [0, 255, 0, 255]);
+        // The green pixel is restored to the previous canvas before the fourth frame.
+        assert_eq!(rendered[3].pixel(0, 0), [0, 0, 0, 0]);
+        assert_eq!(rendered[3].pixel(1, 1), [0, 0, 255, 128]);
+    }
+
+    #[test]
+    fn test_blend_over() {
+        assert_eq!(
+            blend_over([255, 0, 0, 255], [0, 0, 255, 0]),
+            [255, 0, 0, 255]
+        );
+        assert_eq!(
+            blend_over([255, 0, 0, 255], [0, 0, 255, 255]),
+            [0, 0, 255, 255]
+        );
+        assert_eq!(
+            blend_over([255, 0, 0, 255], [0, 0, 255, 128]),
+            [127, 0, 128, 255]
+        );
+    }
+
+    fn animation_frames() -> Vec<AnimationFrame> {
+        [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 128]]
+            .iter()
+            .enumerate()
+            .map(|(index, &rgba)| {
+                let mut image = RgbaImage::new(4, 3);
+                for y in 0..=index as u32 {
+                    image.set_pixel(index as u32, y, rgba);
+                }
+                if index > 0 {
+                    image.set_pixel(0, 0, [255, 0, 0, 255]);
+                    image.set_pixel(1, 1, [0, 255, 0, 255]);
+                }
+                AnimationFrame {
+                    image,
+                    delay_num: index as u16 + 1,
+                    delay_den: 10,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_assemble_round_trip() {
+        let inputs = animation_frames();
+        let options = AssembleOptions {
+            num_plays: 3,
+            ..Default::default()
+        };
+
+        let png = assemble(&inputs, options).unwrap();
+        let png = Png::try_from(png.as_bytes().as_slice()).unwrap();
+
+        assert_eq!(png.animation_control().unwrap().unwrap().num_plays, 3);
+        let frames = png.frames().unwrap();
+        assert!(frames[0].is_default_image);
+        assert_eq!(frames[2].control.delay_secs(), 0.3);
+        let rendered = png.render_frames().unwrap();
+        let expected: Vec<&RgbaImage> = inputs.iter().map(|frame| &frame.image).collect();
+        assert_eq!(rendered.iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_assemble_cropped() {
+        let inputs = animation_frames();
+        let options = AssembleOptions {
+            crop: true,
+            ..Default::default()
+        };
+
+        let png = assemble(&inputs, options).unwrap();
+
+        let frames = png.frames().unwrap();
+        assert_eq!((frames[1].control.width, frames[1].control.height), (1, 2));
+        assert_eq!(
+            (frames[2].control.x_offset, frames[2].control.width),
+            (1, 2)
+        );
+        let rendered = png.render_frames().unwrap();
+        for (rendered, input) in rendered.iter().zip(inputs.iter()) {
+            assert_eq!(rendered, &input.image);
+        }
+    }
+
+    #[test]
+    fn test_assemble_hidden_default_image() {
+        let inputs = animation_frames();
+        let options = AssembleOptions {
+            hidden_default_image: true,
+            ..Default::default()
+        };
+
+        let png = assemble(&inputs, options).unwrap();
+
+        assert_eq!(png.decode_rgba().unwrap(), inputs[0].image);
+        let rendered = png.render_frames().unwrap();
+        assert_eq!(rendered.len(), 2);
+        assert_eq!(rendered[1], inputs[2].image);
+    }
+
+    #[test]
+    fn test_assemble_rejects_mismatched_dimensions() {
+        let mut inputs = animation_frames();
+        inputs[1].image = RgbaImage::new(2, 2);
+
+        assert!(assemble(&inputs, AssembleOptions::default()).is_err());
+        assert!(assemble(&[], AssembleOptions::default()).is_err());
+    }
+}
@@ -1,4 +1,8 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use png_rs::limits::DecodeLimits;
+use png_rs::payload::DEFAULT_PART_SIZE;
+use png_rs::stego::Channel;
 
 
 #[derive(Parser, Debug)]
@@ -7,9 +11,65 @@ pub struct Cli {
     #[clap(subcommand)]
     pub command: Command,
 
-    // NUmber
-    #[arg(short, long, group = "input")]
-    pub top_level_option: String
+    #[command(flatten)]
+    pub limits: LimitArgs,
+
+    /// Deprecated and ignored, still accepted so existing scripts keep working
+    #[arg(short, long, hide = true)]
+    pub top_level_option: Option<String>,
+}
+
+/// Overrides for the limits that protect against malicious files
+#[derive(Args, Debug)]
+pub struct LimitArgs {
+    /// Most bytes of data accepted in a single chunk
+    #[arg(long, global = true)]
+    pub max_chunk_size: Option<u32>,
+
+    /// Largest file accepted, in bytes, for every command
+    #[arg(long, global = true)]
+    pub max_total_size: Option<usize>,
+
+    /// Most chunks accepted in one file
+    #[arg(long, global = true)]
+    pub max_chunks: Option<usize>,
+
+    /// Most bytes the image data may inflate to
+    #[arg(long, global = true)]
+    pub max_decompressed: Option<usize>,
+
+    /// Widest image accepted, in pixels
+    #[arg(long, global = true)]
+    pub max_width: Option<u32>,
+
+    /// Tallest image accepted, in pixels
+    #[arg(long, global = true)]
+    pub max_height: Option<u32>,
+
+    /// Most pixels accepted in one image
+    #[arg(long, global = true)]
+    pub max_pixels: Option<u64>,
+
+    /// Accept files of any size, only use this on trusted files
+    #[arg(long, global = true, conflicts_with_all = [
+        "max_chunk_size", "max_total_size", "max_chunks", "max_decompressed", "max_width", "max_height", "max_pixels",
+    ])]
+    pub no_limits: bool,
+}
+
+impl LimitArgs {
+    pub fn to_decode_limits(&self) -> DecodeLimits {
+        let defaults = if self.no_limits { DecodeLimits::unlimited() } else { DecodeLimits::default() };
+        DecodeLimits {
+            max_chunk_size: self.max_chunk_size.unwrap_or(defaults.max_chunk_size),
+            max_total_size: self.max_total_size.unwrap_or(defaults.max_total_size),
+            max_chunks: self.max_chunks.unwrap_or(defaults.max_chunks),
+            max_decompressed_size: self.max_decompressed.unwrap_or(defaults.max_decompressed_size),
+            max_width: self.max_width.unwrap_or(defaults.max_width),
+            max_height: self.max_height.unwrap_or(defaults.max_height),
+            max_pixels: self.max_pixels.unwrap_or(defaults.max_pixels),
+        }
+    }
 }
 
 #[derive(Subcommand, Debug)]
@@ -18,6 +78,19 @@ pub enum Command {
     Decode(DecodeArgs),
     Remove(RemoveArgs),
     Print(PrintArgs),
+    Strip(StripArgs),
+    ExtractFrames(ExtractFramesArgs),
+    Animate(AnimateArgs),
+    Hide(HideArgs),
+    Reveal(RevealArgs),
+    Capacity(CapacityArgs),
+    Keygen(KeygenArgs),
+    Sign(SignArgs),
+    Verify(VerifyArgs),
+    Hash(HashArgs),
+    Inspect(InspectArgs),
+    Trailer(TrailerArgs),
+    Repair(RepairArgs),
 }
 
 
@@ -26,9 +99,35 @@ pub struct EncodeArgs {
     pub file_path: String,
 
     pub chunk_type: String,
-    pub message: String,
+
+    #[arg(required_unless_present = "message_file")]
+    pub message: Option<String>,
 
     pub output_file: Option<String>,
+
+    /// Embed the contents of this file instead of `message`
+    #[arg(long, conflicts_with = "message")]
+    pub message_file: Option<String>,
+
+    /// Write the PNG to this file, same as `output_file`
+    #[arg(short, long, conflicts_with = "output_file")]
+    pub output: Option<String>,
+
+    /// Deflate the message before embedding it, `decode` inflates it again
+    #[arg(long)]
+    pub compress: bool,
+
+    /// Messages longer than this many bytes are split across several chunks
+    #[arg(long, default_value_t = DEFAULT_PART_SIZE)]
+    pub chunk_size: usize,
+
+    /// Encode even if the chunk type is critical or collides with a standard chunk
+    #[arg(long)]
+    pub force: bool,
+
+    /// Encrypt the message with a key derived from this passphrase
+    #[arg(long)]
+    pub passphrase: Option<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -36,6 +135,45 @@ pub struct DecodeArgs {
     pub file_path: String,
 
     pub chunk_type: String,
+
+    /// Decode every chunk of this type instead of only the first
+    #[arg(long)]
+    pub all: bool,
+
+    /// How the chunk data is written out
+    #[arg(long, value_enum, default_value_t = DecodeFormat::Text)]
+    pub format: DecodeFormat,
+
+    /// Write the decoded data to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Write each message to its own `<chunk type>-<index>` file in this directory
+    #[arg(long, conflicts_with = "output")]
+    pub output_dir: Option<String>,
+
+    /// Decrypt messages written with `encode --passphrase`
+    #[arg(long)]
+    pub passphrase: Option<String>,
+
+    /// Print compressed messages as stored, for payloads that only happen to start
+    /// with the compression header
+    #[arg(long)]
+    pub no_decompress: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum DecodeFormat {
+    /// UTF-8 text, replacing invalid sequences
+    Text,
+    /// UTF-8 text, failing on invalid sequences
+    Utf8,
+    /// The chunk data bytes, unmodified
+    Raw,
+    /// Offset, hex and ASCII columns
+    Hex,
+    /// Standard base64
+    Base64,
 }
 
 #[derive(Parser, Debug)]
@@ -43,9 +181,292 @@ pub struct RemoveArgs {
     pub file_path: String,
 
     pub chunk_type: String,
+
+    /// Remove every chunk of this type instead of only the first
+    #[arg(long, conflicts_with_all = ["index", "nth"])]
+    pub all: bool,
+
+    /// Remove the chunk at this position in the chunk list, as shown by `print`
+    #[arg(long, conflicts_with = "nth")]
+    pub index: Option<usize>,
+
+    /// Remove the Nth (0-based) chunk of this type
+    #[arg(long)]
+    pub nth: Option<usize>,
+
+    /// Allow removing critical chunks such as IHDR, IDAT and IEND
+    #[arg(long)]
+    pub force: bool,
 }
 
 #[derive(Parser, Debug)]
 pub struct PrintArgs {
     pub file_path: String,
-}
\ No newline at end of file
+}
+
+#[derive(Parser, Debug)]
+pub struct StripArgs {
+    pub file_path: String,
+
+    pub output_file: Option<String>,
+
+    /// Ancillary chunk types to keep, e.g. `--keep sRGB,gAMA`
+    #[arg(long, value_delimiter = ',')]
+    pub keep: Vec<String>,
+
+    /// Only strip these chunk types instead of every ancillary chunk
+    #[arg(long, value_delimiter = ',')]
+    pub drop: Vec<String>,
+
+    /// Predefined list of chunk types to strip, combined with `--drop`
+    #[arg(long, value_enum)]
+    pub preset: Option<StripPreset>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum StripPreset {
+    /// Text, EXIF and timestamp chunks
+    Privacy,
+    /// Everything in `privacy` plus color chunks other than sRGB and gAMA
+    Web,
+}
+
+#[derive(Parser, Debug)]
+pub struct ExtractFramesArgs {
+    pub file_path: String,
+
+    /// Directory the frames are written to, as `<name>-<frame>.png`
+    #[arg(default_value = ".")]
+    pub output_dir: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct AnimateArgs {
+    /// PNG files to use as frames, in display order
+    #[arg(required = true)]
+    pub frames: Vec<String>,
+
+    #[arg(short, long)]
+    pub output: String,
+
+    /// Frame delays in milliseconds, the last value is repeated for the remaining frames
+    #[arg(long, value_delimiter = ',', default_value = "100")]
+    pub delay: Vec<u16>,
+
+    /// Number of times to play the animation, 0 loops forever
+    #[arg(long, default_value_t = 0)]
+    pub loops: u32,
+
+    /// Use the first file only as the static image for viewers without APNG support
+    #[arg(long)]
+    pub hidden_first: bool,
+
+    /// Store only the rectangle that changed since the previous frame
+    #[arg(long)]
+    pub crop: bool,
+}
+
+/// Where `hide` and `reveal` put message bits inside the pixels.
+#[derive(Args, Debug)]
+pub struct LayoutArgs {
+    /// Channels that carry message bits, e.g. `--channels r,g,b,a`
+    #[arg(long, value_delimiter = ',', default_value = "r,g,b")]
+    pub channels: Vec<Channel>,
+
+    /// Bit planes that carry message bits, 0 is the least significant bit
+    #[arg(long, value_delimiter = ',', default_value = "0")]
+    pub bits: Vec<u8>,
+}
+
+#[derive(Parser, Debug)]
+pub struct HideArgs {
+    pub file_path: String,
+
+    pub message: String,
+
+    pub output_file: Option<String>,
+
+    /// Key deciding the order the message bits are spread over the image
+    #[arg(long)]
+    pub key: String,
+
+    /// Encrypt the message with a key derived from this passphrase before hiding it
+    #[arg(long)]
+    pub passphrase: Option<String>,
+
+    #[command(flatten)]
+    pub layout: LayoutArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct RevealArgs {
+    pub file_path: String,
+
+    /// The key the message was hidden with
+    #[arg(long)]
+    pub key: String,
+
+    /// Decrypt messages hidden with `hide --passphrase`
+    #[arg(long)]
+    pub passphrase: Option<String>,
+
+    /// Write the message to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[command(flatten)]
+    pub layout: LayoutArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct CapacityArgs {
+    pub file_path: String,
+
+    #[command(flatten)]
+    pub layout: LayoutArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct KeygenArgs {
+    /// Where to write the hex signing key, readable by its owner only. The public
+    /// key goes to `<path>.pub`. Existing keys are not overwritten
+    pub key_file: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct SignArgs {
+    pub file_path: String,
+
+    pub output_file: Option<String>,
+
+    /// File holding the hex signing key written by `keygen`
+    #[arg(long)]
+    pub key: String,
+
+    /// Ancillary chunk types covered by the signature as well as the critical chunks
+    #[arg(long, value_delimiter = ',')]
+    pub protect: Vec<String>,
+
+    /// Write the signature to this file instead of embedding it in the image
+    #[arg(long)]
+    pub detached: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct VerifyArgs {
+    pub file_path: String,
+
+    /// File holding the hex public key the image must be signed with
+    #[arg(long)]
+    pub public_key: Option<String>,
+
+    /// Read a detached signature from this file instead of the image
+    #[arg(long)]
+    pub signature: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct HashArgs {
+    /// PNG files to hash
+    #[arg(required = true)]
+    pub files: Vec<String>,
+
+    /// Only list files whose pixels are identical to another file's
+    #[arg(long)]
+    pub duplicates: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct InspectArgs {
+    pub file_path: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct TrailerArgs {
+    pub file_path: String,
+
+    /// Where `--strip` writes the PNG, defaults to overwriting the input
+    pub output_file: Option<String>,
+
+    /// Write the bytes after IEND to this file
+    #[arg(long, conflicts_with = "strip")]
+    pub extract: Option<String>,
+
+    /// Remove the bytes after IEND
+    #[arg(long)]
+    pub strip: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct RepairArgs {
+    pub file_path: String,
+
+    /// Where to write the repaired PNG, defaults to overwriting the input
+    pub output_file: Option<String>,
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(args: &[&str]) -> DecodeLimits {
+        let cli = Cli::try_parse_from(["png-rs"].iter().chain(args)).unwrap();
+        cli.limits.to_decode_limits()
+    }
+
+    #[test]
+    fn test_limit_options() {
+        assert_eq!(limits(&["print", "a.png"]), DecodeLimits::default());
+        assert_eq!(limits(&["print", "a.png", "--no-limits"]), DecodeLimits::unlimited());
+
+        let raised = limits(&["--max-chunks", "1000000", "hash", "a.png", "--max-pixels", "5"]);
+        assert_eq!(raised.max_chunks, 1_000_000);
+        assert_eq!(raised.max_pixels, 5);
+        assert_eq!(raised.max_decompressed_size, DecodeLimits::default().max_decompressed_size);
+
+        assert!(Cli::try_parse_from(["png-rs", "print", "a.png", "--no-limits", "--max-chunks", "1"]).is_err());
+        assert!(Cli::try_parse_from(["png-rs", "print", "a.png", "--no-limits", "--max-width", "1"]).is_err());
+
+        let every = limits(&[
+            "inspect", "a.png", "--max-chunk-size", "1", "--max-total-size", "2", "--max-chunks", "3",
+            "--max-decompressed", "4", "--max-width", "5", "--max-height", "6", "--max-pixels", "7",
+        ]);
+        assert_eq!(
+            every,
+            DecodeLimits {
+                max_chunk_size: 1,
+                max_total_size: 2,
+                max_chunks: 3,
+                max_decompressed_size: 4,
+                max_width: 5,
+                max_height: 6,
+                max_pixels: 7,
+            }
+        );
+    }
+
+    #[test]
+    fn test_top_level_option_is_still_accepted() {
+        let cli = Cli::try_parse_from(["png-rs", "-t", "value", "print", "a.png"]).unwrap();
+        assert_eq!(cli.top_level_option.as_deref(), Some("value"));
+
+        let cli = Cli::try_parse_from(["png-rs", "print", "a.png"]).unwrap();
+        assert_eq!(cli.top_level_option, None);
+    }
+
+    #[test]
+    fn test_strip_options() {
+        let cli = Cli::try_parse_from([
+            "png-rs", "strip", "a.png", "--keep", "sRGB,gAMA", "--drop", "tEXt", "--drop", "zTXt", "--preset", "web",
+        ])
+        .unwrap();
+        let Command::Strip(args) = cli.command else { panic!("expected strip") };
+
+        assert_eq!(args.keep, ["sRGB", "gAMA"]);
+        assert_eq!(args.drop, ["tEXt", "zTXt"]);
+        assert!(matches!(args.preset, Some(StripPreset::Web)));
+        assert!(Cli::try_parse_from(["png-rs", "strip", "a.png", "--preset", "everything"]).is_err());
+    }
+}
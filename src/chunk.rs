@@ -1,9 +1,10 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names, clippy::needless_borrow)]
+
 use crate::chunk_type::ChunkType;
+use crate::limits::DecodeLimits;
 
-use crc;
 use std::{
     fmt,
-    fs::read,
     io::{BufRead, BufReader, Read},
 };
 
@@ -69,22 +70,29 @@ impl Chunk {
         return output;
     }
 
-    pub fn read_chunk(reader: &mut BufReader<&[u8]>) -> Result<Chunk, String> {
+    /// Reads one chunk, rejecting lengths over `limits` before allocating anything.
+    pub fn read_chunk(reader: &mut BufReader<&[u8]>, limits: &DecodeLimits) -> Result<Chunk, String> {
         let mut buffer = [0; 4];
 
         reader.read_exact(&mut buffer).map_err(|_x| "Error".to_string())?;
 
         let length = u32::from_be_bytes(buffer);
+        limits.check_chunk_length(length)?;
 
         reader
             .read_exact(&mut buffer)
             .map_err(|_x| "Error".to_string())?;
-        let chunk_type:ChunkType = buffer.try_into()?;
+        let chunk_type = ChunkType::new_lenient(buffer)?;
 
-        let mut data = vec![0; length as usize];
+        // Read through `take` so a truncated chunk cannot make us allocate its claimed length.
+        let mut data = Vec::new();
         reader
-            .read_exact(&mut data)
+            .take(length as u64)
+            .read_to_end(&mut data)
             .map_err(|_x| "Error".to_string())?;
+        if data.len() != length as usize {
+            return Err("Error".to_string());
+        }
 
         reader
             .read_exact(&mut buffer)
@@ -112,14 +120,12 @@ impl TryFrom<&[u8]> for Chunk {
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
         let mut reader = BufReader::new(value);
-        let chunk = Self::read_chunk(&mut reader)?;
+        let chunk = Self::read_chunk(&mut reader, &DecodeLimits::default())?;
 
         if !reader.fill_buf().unwrap().is_empty() {
             return Err("invalid chunk".to_string());
         }
 
-        println!("Chunk len {}",chunk.length());
-
         Ok(chunk)
     }
 }
@@ -130,12 +136,163 @@ impl fmt::Display for Chunk {
     }
 }
 
+/// A chunk borrowed from a larger buffer, such as a whole file in memory. Nothing is
+/// copied until `to_chunk` is called, and the CRC is only checked when asked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkRef<'a> {
+    chunk_type: ChunkType,
+    data: &'a [u8],
+    crc: u32,
+    raw: &'a [u8],
+}
+
+impl<'a> ChunkRef<'a> {
+    /// Reads the chunk at the start of `bytes` without checking its CRC. Returns the
+    /// chunk and the number of bytes it takes up.
+    pub fn parse(bytes: &'a [u8], limits: &DecodeLimits) -> Result<(ChunkRef<'a>, usize), String> {
+        if bytes.len() < 8 {
+            return Err("chunk header is truncated".to_string());
+        }
+        let length = u32::from_be_bytes(bytes[..4].try_into().unwrap());
+        limits.check_chunk_length(length)?;
+        let chunk_type = ChunkType::new_lenient(bytes[4..8].try_into().unwrap())?;
+
+        let end = 12 + length as usize;
+        if bytes.len() < end {
+            return Err(format!(
+                "{} chunk is truncated, expected {} bytes but found {}",
+                chunk_type,
+                end,
+                bytes.len()
+            ));
+        }
+
+        let chunk = ChunkRef {
+            chunk_type,
+            data: &bytes[8..end - 4],
+            crc: u32::from_be_bytes(bytes[end - 4..end].try_into().unwrap()),
+            raw: &bytes[..end],
+        };
+        Ok((chunk, end))
+    }
+
+    pub fn length(&self) -> u32 {
+        self.data.len() as u32
+    }
+
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The CRC stored in the file, which may not match the data.
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    /// The whole chunk as stored: length, type, data and CRC.
+    pub fn raw(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    /// Computes the CRC of the type and data, without concatenating them.
+    pub fn computed_crc(&self) -> u32 {
+        let crc = crc::crc32::update(0, &crc::crc32::IEEE_TABLE, &self.chunk_type.bytes());
+        crc::crc32::update(crc, &crc::crc32::IEEE_TABLE, self.data)
+    }
+
+    pub fn check_crc(&self) -> Result<(), String> {
+        let computed = self.computed_crc();
+        if computed != self.crc {
+            return Err(format!(
+                "{} chunk has crc {:08x} but its data has crc {:08x}",
+                self.chunk_type, self.crc, computed
+            ));
+        }
+        Ok(())
+    }
+
+    /// Copies this chunk into an owned `Chunk`. The copy gets a freshly computed CRC, so
+    /// call `check_crc` first to refuse corrupt chunks.
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(self.chunk_type.clone(), self.data.to_vec())
+    }
+}
+
+impl fmt::Display for ChunkRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Chunk {} data_len:{} crc:{}", self.chunk_type, self.data.len(), self.crc())
+    }
+}
+
+/// Read access shared by `Chunk` and `ChunkRef`, for code that only inspects chunks
+/// and should work on owned and borrowed files alike.
+pub trait ChunkView {
+    fn chunk_type(&self) -> &ChunkType;
+    fn data(&self) -> &[u8];
+}
+
+impl ChunkView for Chunk {
+    fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl ChunkView for ChunkRef<'_> {
+    fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+
+    fn data(&self) -> &[u8] {
+        self.data
+    }
+}
+
+/// Proptest strategies for chunks, shared with the tests of modules built on `Chunk`.
+#[cfg(test)]
+pub(crate) mod strategies {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Any four letters, as accepted by `read_chunk`. A few common types are picked
+    /// often so that lists of chunks contain repeated types.
+    pub fn chunk_type() -> impl Strategy<Value = ChunkType> {
+        let letter = prop_oneof![b'A'..=b'Z', b'a'..=b'z'];
+        prop_oneof![
+            prop::array::uniform4(letter),
+            prop::sample::select(vec![*b"tEXt", *b"IDAT", *b"ruSt"]),
+        ]
+        .prop_map(|bytes| ChunkType::new_lenient(bytes).unwrap())
+    }
+
+    pub fn chunk() -> impl Strategy<Value = Chunk> {
+        (chunk_type(), prop::collection::vec(any::<u8>(), 0..256))
+            .prop_map(|(chunk_type, data)| Chunk::new(chunk_type, data))
+    }
+
+    /// Chunks other than IEND, which ends parsing.
+    pub fn chunk_before_iend() -> impl Strategy<Value = Chunk> {
+        chunk().prop_filter("IEND ends the file", |chunk| {
+            chunk.chunk_type().to_string() != "IEND"
+        })
+    }
+}
+
 // Unit Tests
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::chunk_type::ChunkType;
+    use proptest::prelude::*;
+    use proptest::sample::Index;
     use std::str::FromStr;
 
     fn testing_chunk() -> Chunk {
@@ -262,4 +419,73 @@ mod tests {
 
         let _chunk_string = format!("{}", chunk);
     }
+
+    #[test]
+    fn test_read_chunk_rejects_oversized_lengths() {
+        let chunk_data: Vec<u8> = [&u32::MAX.to_be_bytes()[..], b"RuSt", b"data"].concat();
+        let mut reader = BufReader::new(chunk_data.as_slice());
+        let error = Chunk::read_chunk(&mut reader, &DecodeLimits::unlimited()).unwrap_err();
+        assert!(error.contains("2^31 - 1"));
+
+        // A length under the cap but over the limit is refused too.
+        let chunk_data: Vec<u8> = [&1000u32.to_be_bytes()[..], b"RuSt", b"data"].concat();
+        let limits = DecodeLimits {
+            max_chunk_size: 999,
+            ..DecodeLimits::default()
+        };
+        let mut reader = BufReader::new(chunk_data.as_slice());
+        assert!(Chunk::read_chunk(&mut reader, &limits).is_err());
+
+        // A truncated chunk claiming 2 GiB fails without allocating it.
+        let chunk_data: Vec<u8> = [&i32::MAX.to_be_bytes()[..], b"RuSt", b"data"].concat();
+        let mut reader = BufReader::new(chunk_data.as_slice());
+        assert!(Chunk::read_chunk(&mut reader, &DecodeLimits::unlimited()).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn test_chunk_round_trip(chunk in strategies::chunk()) {
+            let bytes = chunk.as_bytes();
+
+            prop_assert_eq!(Chunk::try_from(bytes.as_slice()).unwrap(), chunk);
+        }
+
+        #[test]
+        fn test_any_bit_flip_is_detected(chunk in strategies::chunk(), bit in any::<Index>()) {
+            let mut bytes = chunk.as_bytes();
+            let bit = bit.index(bytes.len() * 8);
+            bytes[bit / 8] ^= 1 << (bit % 8);
+
+            prop_assert!(Chunk::try_from(bytes.as_slice()).is_err());
+        }
+
+        #[test]
+        fn test_wrong_crc_is_detected(chunk in strategies::chunk(), crc in any::<u32>()) {
+            prop_assume!(crc != chunk.crc());
+            let mut bytes = chunk.as_bytes();
+            let crc_start = bytes.len() - 4;
+            bytes[crc_start..].copy_from_slice(&crc.to_be_bytes());
+
+            prop_assert!(Chunk::try_from(bytes.as_slice()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_chunk_ref_borrows_and_checks_crc_lazily() {
+        let chunk = testing_chunk();
+        let bytes = [chunk.as_bytes(), b"next chunk".to_vec()].concat();
+
+        let (chunk_ref, used) = ChunkRef::parse(&bytes, &DecodeLimits::default()).unwrap();
+        assert_eq!(used, chunk.as_bytes().len());
+        assert_eq!(chunk_ref.raw(), chunk.as_bytes());
+        assert_eq!(chunk_ref.data().as_ptr(), bytes[8..].as_ptr());
+        assert!(chunk_ref.check_crc().is_ok());
+        assert_eq!(chunk_ref.to_chunk(), chunk);
+
+        let mut corrupt = chunk.as_bytes();
+        corrupt[10] ^= 1;
+        let (chunk_ref, _) = ChunkRef::parse(&corrupt, &DecodeLimits::default()).unwrap();
+        assert!(chunk_ref.check_crc().unwrap_err().contains("crc"));
+        assert!(ChunkRef::parse(&corrupt[..20], &DecodeLimits::default()).is_err());
+    }
 }
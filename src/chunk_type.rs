@@ -1,14 +1,46 @@
+#![allow(clippy::needless_return)]
+
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::str;
 use std::str::FromStr;
 
-#[derive(Debug, PartialEq)]
+use crate::registry::{self, Origin};
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ChunkType {
     bytes: [u8; 4],
 }
 
 // check bit 4 of byte 1 ... critical
 impl ChunkType {
+    /// Creates a `ChunkType` that is valid for writing: four ASCII letters with
+    /// an uppercase (reserved) third letter.
+    pub fn new(bytes: [u8; 4]) -> Result<Self, &'static str> {
+        let chunk_type = Self::new_lenient(bytes)?;
+        if !chunk_type.is_reserved_bit_valid() {
+            return Err("Reserved bit set, third letter must be uppercase");
+        }
+        Ok(chunk_type)
+    }
+
+    /// Creates a `ChunkType` from four ASCII letters without checking the reserved
+    /// bit. The spec asks decoders to accept such chunks, so this is used when reading.
+    pub fn new_lenient(bytes: [u8; 4]) -> Result<Self, &'static str> {
+        if !bytes.iter().all(u8::is_ascii_alphabetic) {
+            return Err("Non Alphabetic character");
+        }
+        Ok(ChunkType { bytes })
+    }
+
+    /// Parses a chunk type name like `FromStr`, but without checking the reserved bit.
+    pub fn from_str_lenient(s: &str) -> Result<Self, &'static str> {
+        Self::new_lenient(Self::name_to_bytes(s)?)
+    }
+
+    fn name_to_bytes(s: &str) -> Result<[u8; 4], &'static str> {
+        s.as_bytes().try_into().map_err(|_| "Expected size of 4")
+    }
+
     pub fn bytes(&self) -> [u8; 4] {
         self.bytes
     }
@@ -21,19 +53,28 @@ impl ChunkType {
         return true;
     }
 
-    fn is_critical(&self) -> bool {
+    /// True if this chunk type is defined by the PNG specification or a registered extension.
+    pub fn is_standard(&self) -> bool {
+        registry::lookup(self).is_some_and(|info| info.origin != Origin::ThirdParty)
+    }
+
+    /// Critical chunks (uppercase first letter) are required to display the image.
+    pub fn is_critical(&self) -> bool {
         (self.bytes[0] & 1 << 5) == 0
     }
 
-    fn is_public(&self) -> bool {
+    /// Public chunks (uppercase second letter) are reserved for types registered with the PNG group.
+    pub fn is_public(&self) -> bool {
         (self.bytes[1] & 1 << 5) == 0
     }
 
-    fn is_reserved_bit_valid(&self) -> bool {
+    /// The third letter must be uppercase in files conforming to the current spec.
+    pub fn is_reserved_bit_valid(&self) -> bool {
         (self.bytes[2] & 1 << 5) == 0
     }
 
-    fn is_safe_to_copy(&self) -> bool {
+    /// Safe-to-copy chunks (lowercase fourth letter) may be kept by editors that modify the image.
+    pub fn is_safe_to_copy(&self) -> bool {
         (self.bytes[3] & 1 << 5) != 0
     }
 }
@@ -42,7 +83,7 @@ impl TryFrom<[u8; 4]> for ChunkType {
     type Error = &'static str; // how does this work?
 
     fn try_from(value: [u8; 4]) -> Result<Self, Self::Error> {
-        Ok(ChunkType { bytes: value })
+        Self::new(value)
     }
 }
 
@@ -50,30 +91,7 @@ impl FromStr for ChunkType {
     type Err = &'static str;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.len() != 4 {
-            return Err("Expected size of 4");
-        }
-
-        let mut bytes: [u8; 4] = [0, 0, 0, 0];
-
-        for i in 0..4 {
-            let c = s.bytes().nth(i);
-
-            match c {
-                Some(val) => {
-                    if !val.is_ascii_alphabetic() {
-                        return Err("Non Alphabetic character");
-                    }
-                    bytes[i] = val;
-                }
-
-                None => return Err("Could not decode byte"),
-            }
-        }
-
-        let proposed_chunk = ChunkType { bytes: bytes };
-
-        return Ok(proposed_chunk);
+        Self::new(Self::name_to_bytes(s)?)
     }
 }
 
@@ -83,8 +101,7 @@ impl Display for ChunkType {
     }
 }
 
-//// Unit Tests
-///
+// Unit Tests
 
 #[cfg(test)]
 mod tests {
@@ -139,7 +156,7 @@ mod tests {
 
     #[test]
     pub fn test_chunk_type_is_reserved_bit_invalid() {
-        let chunk = ChunkType::from_str("Rust").unwrap();
+        let chunk = ChunkType::from_str_lenient("Rust").unwrap();
         assert!(!chunk.is_reserved_bit_valid());
     }
 
@@ -163,13 +180,35 @@ mod tests {
 
     #[test]
     pub fn test_invalid_chunk_is_valid() {
-        let chunk = ChunkType::from_str("Rust").unwrap();
+        let chunk = ChunkType::from_str_lenient("Rust").unwrap();
         assert!(!chunk.is_valid());
 
         let chunk = ChunkType::from_str("Ru1t");
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_strict_constructors_reject_reserved_bit() {
+        assert!(ChunkType::from_str("Rust").is_err());
+        assert!(ChunkType::try_from(*b"Rust").is_err());
+        assert!(ChunkType::new_lenient(*b"Rust").is_ok());
+    }
+
+    #[test]
+    pub fn test_constructors_reject_non_letters() {
+        assert!(ChunkType::try_from([82, 117, 0, 116]).is_err());
+        assert!(ChunkType::new_lenient(*b"Ru1t").is_err());
+        assert!(ChunkType::from_str_lenient("Ru t").is_err());
+        assert!(ChunkType::from_str("RuStt").is_err());
+    }
+
+    #[test]
+    pub fn test_chunk_type_is_standard() {
+        assert!(ChunkType::from_str("IHDR").unwrap().is_standard());
+        assert!(ChunkType::from_str("tEXt").unwrap().is_standard());
+        assert!(!ChunkType::from_str("RuSt").unwrap().is_standard());
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
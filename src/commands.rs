@@ -1,19 +1,478 @@
-use std::{fs, str::FromStr};
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    str::FromStr,
+};
 
-use crate::{
-    args::{DecodeArgs, EncodeArgs, PrintArgs, RemoveArgs},
-    chunk::Chunk,
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::aead::OsRng;
+use ed25519_dalek::SigningKey;
+
+use png_rs::{
+    apng::{self, AnimationFrame, AssembleOptions},
+    chunk::{Chunk, ChunkRef},
     chunk_type::ChunkType,
+    crypto,
+    edit::{self, TailEdit},
+    ihdr::ImageHeader,
+    image, inspect,
+    limits::DecodeLimits,
+    payload,
     png::Png,
+    recover, registry,
+    signature::{self, Signature},
+    stego::{self, Layout},
+};
+
+use crate::{
+    args::{
+        AnimateArgs, CapacityArgs, DecodeArgs, DecodeFormat, EncodeArgs, ExtractFramesArgs,
+        HashArgs, HideArgs, InspectArgs, KeygenArgs, LayoutArgs, PrintArgs, RemoveArgs, RepairArgs,
+        RevealArgs, SignArgs, StripArgs, StripPreset, TrailerArgs, VerifyArgs,
+    },
     Result,
 };
 
-pub fn encode(args: &EncodeArgs) -> Result<()> {
-    let mut png: Png = fs::read(&args.file_path)?.as_slice().try_into()?;
-    png.append_chunk(Chunk::new(
-        ChunkType::from_str(&args.chunk_type)?,
-        args.message.as_bytes().into(),
-    ));
+/// Chunk types that can identify the author, device or time of capture.
+const PRIVACY_CHUNKS: [&str; 5] = ["tEXt", "zTXt", "iTXt", "eXIf", "tIME"];
+
+/// Color chunks dropped by the web preset; sRGB and gAMA are deliberately kept.
+const WEB_COLOR_CHUNKS: [&str; 6] = ["cHRM", "iCCP", "sBIT", "cICP", "mDCV", "cLLI"];
+
+/// Number of trailer bytes `trailer` shows as a hex dump.
+const TRAILER_PREVIEW: usize = 256;
+
+pub fn encode(args: &EncodeArgs, limits: DecodeLimits) -> Result<()> {
+    let chunk_type = ChunkType::from_str(&args.chunk_type)?;
+    check_message_chunk_type(&chunk_type, args.force)?;
+
+    let message = match (&args.message, &args.message_file) {
+        (_, Some(path)) => fs::read(path)?,
+        (Some(message), None) => message.as_bytes().into(),
+        (None, None) => return Err("no message given, use `message` or --message-file".into()),
+    };
+    // Compress before encrypting, ciphertext does not compress.
+    let message = if args.compress {
+        compress_message(message)
+    } else {
+        message
+    };
+    let data = match &args.passphrase {
+        Some(passphrase) => crypto::seal(passphrase.as_bytes(), &message)?,
+        None => message,
+    };
+
+    let chunks = if data.len() > args.chunk_size {
+        let parts = payload::split(&data, args.chunk_size)?;
+        println!(
+            "split {} bytes across {} {} chunks",
+            data.len(),
+            parts.len(),
+            chunk_type
+        );
+        parts
+            .into_iter()
+            .map(|part| Chunk::new(chunk_type.clone(), part))
+            .collect()
+    } else {
+        vec![Chunk::new(chunk_type, data)]
+    };
+
+    let output = args.output.as_ref().or(args.output_file.as_ref());
+    let edit = TailEdit {
+        insert_before_iend: chunks,
+        ..TailEdit::default()
+    };
+    // Writing back to the same file only needs the tail from IEND on rewritten.
+    if output.is_none_or(|output| output == &args.file_path)
+        && edit::apply_in_place(&args.file_path, &edit, edit::DEFAULT_MAX_TAIL, limits)?.is_some()
+    {
+        return Ok(());
+    }
+
+    let mut png = Png::from_file_with_limits(&args.file_path, limits)?;
+    for chunk in edit.insert_before_iend {
+        png.insert_chunk_before_iend(chunk);
+    }
+    fs::write(output.unwrap_or(&args.file_path), png.as_bytes())?;
+
+    Ok(())
+}
+
+/// Deflates `message` and reports the ratio, keeping the original if compression
+/// does not make it smaller.
+fn compress_message(message: Vec<u8>) -> Vec<u8> {
+    let compressed = payload::compress(&message);
+    if compressed.len() >= message.len() {
+        eprintln!(
+            "warning: compression would grow the message from {} to {} bytes, storing it uncompressed",
+            message.len(),
+            compressed.len()
+        );
+        return message;
+    }
+
+    println!(
+        "compressed {} bytes to {} ({:.1}% of the original)",
+        message.len(),
+        compressed.len(),
+        100.0 * compressed.len() as f64 / message.len() as f64
+    );
+    compressed
+}
+
+/// Rejects chunk types that would change how decoders treat the image, unless `force`
+/// is set, and warns about types squatting on the public namespace.
+fn check_message_chunk_type(chunk_type: &ChunkType, force: bool) -> Result<()> {
+    let mut problems = Vec::new();
+    if chunk_type.is_standard() {
+        problems.push(format!(
+            "{} is a standard chunk type and would be interpreted by decoders",
+            chunk_type
+        ));
+    }
+    if chunk_type.is_critical() {
+        problems.push(format!(
+            "{} is critical, decoders will refuse to display the image",
+            chunk_type
+        ));
+    }
+
+    if !force {
+        if let Some(problem) = problems.first() {
+            return Err(format!("{}, use --force to override", problem).into());
+        }
+    }
+    for problem in problems {
+        eprintln!("warning: {}", problem);
+    }
+
+    if chunk_type.is_public() && !chunk_type.is_standard() {
+        eprintln!(
+            "warning: {} is in the public namespace but not registered, prefer a lowercase second letter",
+            chunk_type
+        );
+    }
+
+    Ok(())
+}
+
+pub fn decode(args: &DecodeArgs, limits: DecodeLimits) -> Result<()> {
+    let mapped = Png::open_mmap_with_limits(&args.file_path, limits)?;
+    mapped.verify_crcs()?;
+    let png = mapped.png_ref()?;
+
+    let mut chunks: Vec<&ChunkRef> = png.chunks_by_type(&args.chunk_type).collect();
+    // Older versions of `encode` appended messages after IEND.
+    let trailer_chunks = png.trailer_chunks(mapped.limits()).unwrap_or_default();
+    if chunks.is_empty() {
+        chunks = trailer_chunks
+            .iter()
+            .filter(|chunk| chunk.chunk_type().to_string() == args.chunk_type)
+            .collect();
+        if !chunks.is_empty() {
+            eprintln!(
+                "warning: {} chunks found after IEND, where older versions of encode put them",
+                args.chunk_type
+            );
+        }
+    }
+
+    // Parts of a split message are reassembled into one payload, wherever they are,
+    // and every other chunk is a payload of its own. Without --all only the message
+    // starting at the first chunk is decoded.
+    let mut messages = payload::join_messages(chunks.into_iter().map(ChunkRef::data));
+    if messages.is_empty() {
+        return Err(format!("non-existent chunk type {}", args.chunk_type).into());
+    }
+    if !args.all {
+        messages.truncate(1);
+    }
+
+    let mut payloads = Vec::new();
+    let mut failures = Vec::new();
+    for message in messages {
+        match message {
+            Ok(joined) => {
+                if joined.reordered {
+                    eprintln!(
+                        "warning: {} message parts were out of order",
+                        args.chunk_type
+                    );
+                }
+                payloads.push(joined.message);
+            }
+            Err(e) => failures.push(e),
+        }
+    }
+    if payloads.is_empty() {
+        return Err(failures.remove(0).into());
+    }
+    for e in failures {
+        eprintln!("warning: skipping a {} message: {}", args.chunk_type, e);
+    }
+
+    let payloads = payloads
+        .into_iter()
+        .map(|data| {
+            let data = match &args.passphrase {
+                Some(passphrase) => crypto::open(passphrase.as_bytes(), &data)?,
+                None => {
+                    if crypto::is_sealed(&data) {
+                        eprintln!(
+                            "warning: {} chunk is encrypted, use --passphrase",
+                            args.chunk_type
+                        );
+                    }
+                    data
+                }
+            };
+            if args.no_decompress || !payload::is_compressed(&data) {
+                return Ok(data);
+            }
+            payload::decompress(&data, &limits).map_err(|e| {
+                format!(
+                    "{} chunk looks compressed but {}, use --no-decompress to print it as stored",
+                    args.chunk_type, e
+                )
+            })
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    if let Some(output_dir) = &args.output_dir {
+        fs::create_dir_all(output_dir)?;
+        for (index, payload) in payloads.iter().enumerate() {
+            let path = Path::new(output_dir).join(format!(
+                "{}-{:03}.{}",
+                args.chunk_type,
+                index,
+                format_extension(args.format)
+            ));
+            fs::write(&path, format_payload(args, index, payload)?)?;
+            println!("wrote {}", path.display());
+        }
+        return Ok(());
+    }
+
+    // Raw bytes have nothing to tell where one message ends and the next begins.
+    if payloads.len() > 1 && args.format == DecodeFormat::Raw {
+        return Err(format!(
+            "found {} {} messages, use --output-dir to write raw messages to separate files",
+            payloads.len(),
+            args.chunk_type
+        )
+        .into());
+    }
+
+    let mut output = Vec::new();
+    for (index, payload) in payloads.iter().enumerate() {
+        // Hex dump offsets restart for every message, so label each one.
+        if payloads.len() > 1 && args.format == DecodeFormat::Hex {
+            output.extend_from_slice(
+                format!(
+                    "{} message {}, {} bytes:\n",
+                    args.chunk_type,
+                    index,
+                    payload.len()
+                )
+                .as_bytes(),
+            );
+        }
+        output.extend_from_slice(&format_payload(args, index, payload)?);
+    }
+
+    match &args.output {
+        Some(path) => fs::write(path, output)?,
+        None => io::stdout().write_all(&output)?,
+    }
+
+    Ok(())
+}
+
+/// One decoded message in the requested format, with a final newline for text.
+fn format_payload(args: &DecodeArgs, index: usize, payload: &[u8]) -> Result<Vec<u8>> {
+    let mut output = match args.format {
+        DecodeFormat::Raw => payload.to_vec(),
+        DecodeFormat::Text => String::from_utf8_lossy(payload).into_owned().into_bytes(),
+        DecodeFormat::Utf8 => {
+            let text = std::str::from_utf8(payload).map_err(|e| {
+                format!(
+                    "{} chunk {} is not valid UTF-8: {}",
+                    args.chunk_type, index, e
+                )
+            })?;
+            text.as_bytes().to_vec()
+        }
+        DecodeFormat::Hex => hex_dump(payload).into_bytes(),
+        DecodeFormat::Base64 => BASE64.encode(payload).into_bytes(),
+    };
+
+    if args.format != DecodeFormat::Raw && args.format != DecodeFormat::Hex {
+        output.push(b'\n');
+    }
+    Ok(output)
+}
+
+fn format_extension(format: DecodeFormat) -> &'static str {
+    match format {
+        DecodeFormat::Text | DecodeFormat::Utf8 => "txt",
+        DecodeFormat::Raw => "bin",
+        DecodeFormat::Hex => "hex",
+        DecodeFormat::Base64 => "b64",
+    }
+}
+
+pub fn remove(args: &RemoveArgs, limits: DecodeLimits) -> Result<()> {
+    let chunk_type = ChunkType::from_str(&args.chunk_type)?;
+
+    if chunk_type.is_critical() && !args.force {
+        return Err(format!(
+            "refusing to remove critical chunk {}, use --force to override",
+            chunk_type
+        )
+        .into());
+    }
+
+    // Pick the chunks from a mapping so ancillary chunks near the end of a large file
+    // can be removed without reading all of it.
+    let mapped = Png::open_mmap_with_limits(&args.file_path, limits)?;
+    let png = mapped.png_ref()?;
+    let matches: Vec<usize> = png
+        .chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, chunk)| chunk.chunk_type() == &chunk_type)
+        .map(|(index, _)| index)
+        .collect();
+
+    let removed = if args.all {
+        matches.clone()
+    } else if let Some(index) = args.index {
+        match png.chunks().get(index) {
+            Some(_) if matches.contains(&index) => vec![index],
+            Some(chunk) => {
+                return Err(format!(
+                    "chunk at index {} is {}, not {}",
+                    index,
+                    chunk.chunk_type(),
+                    chunk_type
+                )
+                .into())
+            }
+            None => return Err(format!("no chunk at index {}", index).into()),
+        }
+    } else {
+        let nth = args.nth.unwrap_or(0);
+        let index = matches
+            .get(nth)
+            .ok_or_else(|| format!("no {} chunk with occurrence {}", chunk_type, nth))?;
+        vec![*index]
+    };
+
+    if removed.is_empty() {
+        return Err(format!("non-existent chunk type {}", chunk_type).into());
+    }
+
+    for &index in removed.iter() {
+        println!("removed {}", png.chunks()[index]);
+    }
+
+    let remaining = matches.len() - removed.len();
+    if remaining > 0 {
+        println!(
+            "{} more {} chunk(s) remain, use --all to remove them",
+            remaining, chunk_type
+        );
+    }
+    drop(mapped);
+
+    let edit = TailEdit {
+        remove: removed,
+        ..TailEdit::default()
+    };
+    if edit::apply_in_place(&args.file_path, &edit, edit::DEFAULT_MAX_TAIL, limits)?.is_some() {
+        return Ok(());
+    }
+
+    // Critical chunks and chunks far from the end need the whole file rewritten.
+    let mut png = Png::from_file_with_limits(&args.file_path, limits)?;
+    for &index in edit.remove.iter().rev() {
+        png.remove_chunk_at(index)?;
+    }
+    fs::write(&args.file_path, png.as_bytes())?;
+    Ok(())
+}
+
+pub fn print(args: &PrintArgs, limits: DecodeLimits) -> Result<()> {
+    // Mapped so listing a huge file only reads its chunk headers and CRCs.
+    let mapped = Png::open_mmap_with_limits(&args.file_path, limits)?;
+    mapped.verify_crcs()?;
+    let png = mapped.png_ref()?;
+
+    println!("PNG with {} chunks", png.chunks().len());
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let description = match registry::lookup(chunk.chunk_type()) {
+            Some(info) => info.description,
+            None if chunk.chunk_type().is_critical() => "unknown critical chunk",
+            None => "unknown ancillary chunk",
+        };
+        println!("{:>4}  {}  ({})", index, chunk, description);
+    }
+
+    if let Some(control) = png.animation_control()? {
+        println!(
+            "animation: {} frames, {}",
+            control.num_frames,
+            match control.num_plays {
+                0 => "loops forever".to_string(),
+                plays => format!("plays {} time(s)", plays),
+            }
+        );
+        match png.frames() {
+            Ok(frames) => {
+                for (index, frame) in frames.iter().enumerate() {
+                    println!("frame {:>3}  {}", index, frame);
+                }
+            }
+            Err(e) => println!("warning: invalid animation: {}", e),
+        }
+    }
+
+    if !png.trailer().is_empty() {
+        println!(
+            "trailer: {} bytes after IEND, see `trailer`",
+            png.trailer().len()
+        );
+    }
+
+    for problem in registry::validate(png.chunks()) {
+        println!("warning: {}", problem);
+    }
+
+    Ok(())
+}
+
+pub fn strip(args: &StripArgs, limits: DecodeLimits) -> Result<()> {
+    let mut png = Png::from_file_with_limits(&args.file_path, limits)?;
+
+    let keep = parse_chunk_selectors(&args.keep)?;
+    let mut drop = parse_chunk_selectors(&args.drop)?;
+    if let Some(preset) = args.preset {
+        drop.extend(parse_chunk_types(&preset_chunk_types(preset))?);
+    }
+
+    for chunk_type in drop.iter().filter(|chunk_type| chunk_type.is_critical()) {
+        eprintln!("warning: refusing to strip critical chunk {}", chunk_type);
+    }
+
+    let removed = png.strip_chunks(|chunk_type| {
+        !keep.contains(chunk_type) && (drop.is_empty() || drop.contains(chunk_type))
+    });
+
+    for chunk in removed.iter() {
+        println!("stripped {}", chunk);
+    }
 
     fs::write(
         args.output_file.as_ref().unwrap_or(&args.file_path),
@@ -23,27 +482,725 @@ pub fn encode(args: &EncodeArgs) -> Result<()> {
     Ok(())
 }
 
-pub fn decode(args: &DecodeArgs) -> Result<()> {
-    let png: Png = fs::read(&args.file_path)?.as_slice().try_into()?;
-    match png.chunk_by_type(&args.chunk_type) {
-        Some(chunk) => println!("{}", String::from_utf8_lossy(chunk.data())),
-        None => eprintln!("non-existent chunk type"),
+pub fn extract_frames(args: &ExtractFramesArgs, limits: DecodeLimits) -> Result<()> {
+    let png = Png::from_file_with_limits(&args.file_path, limits)?;
+    let frames = png.render_frames()?;
+
+    let stem = Path::new(&args.file_path)
+        .file_stem()
+        .map_or("frame".into(), |stem| stem.to_string_lossy());
+    fs::create_dir_all(&args.output_dir)?;
+
+    for (index, frame) in frames.iter().enumerate() {
+        let path = Path::new(&args.output_dir).join(format!("{}-{:03}.png", stem, index));
+        fs::write(&path, frame.to_png().as_bytes())?;
+        println!("wrote {}", path.display());
     }
 
     Ok(())
 }
 
-pub fn remove(args: &RemoveArgs) -> Result<()> {
-    let mut png: Png = fs::read(&args.file_path)?.as_slice().try_into()?;
-    png.remove_chunk(&args.chunk_type)?;
+pub fn animate(args: &AnimateArgs, limits: DecodeLimits) -> Result<()> {
+    let frames = args
+        .frames
+        .iter()
+        .enumerate()
+        .map(|(index, path)| {
+            let delay = args.delay.get(index).or(args.delay.last()).copied();
+            Ok(AnimationFrame {
+                image: Png::from_file_with_limits(path, limits)?.decode_rgba()?,
+                delay_num: delay.unwrap_or(100),
+                delay_den: 1000,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-    fs::write(&args.file_path, png.as_bytes())?;
+    let png = apng::assemble(
+        &frames,
+        AssembleOptions {
+            num_plays: args.loops,
+            hidden_default_image: args.hidden_first,
+            crop: args.crop,
+        },
+    )?;
+    fs::write(&args.output, png.as_bytes())?;
+
+    Ok(())
+}
+
+pub fn hide(args: &HideArgs, limits: DecodeLimits) -> Result<()> {
+    let mut png = Png::from_file_with_limits(&args.file_path, limits)?;
+    let (header, mut raw) = decode_pixels(&png)?;
+    let layout = layout(&args.layout);
+
+    let message = match &args.passphrase {
+        Some(passphrase) => crypto::seal(passphrase.as_bytes(), args.message.as_bytes())?,
+        None => args.message.as_bytes().into(),
+    };
+    let capacity = stego::capacity(&header, &layout)?;
+    stego::embed(
+        &header,
+        &mut raw,
+        &layout,
+        &stego::derive_seed(args.key.as_bytes())?,
+        &message,
+    )?;
+
+    png.set_image_data(header, image::encode_pixels(&header, &raw)?)?;
+    fs::write(
+        args.output_file.as_ref().unwrap_or(&args.file_path),
+        png.as_bytes(),
+    )?;
+
+    println!(
+        "hid {} bytes, {:.1}% of the {} byte capacity",
+        message.len(),
+        100.0 * message.len() as f64 / capacity.max(1) as f64,
+        capacity
+    );
+    Ok(())
+}
+
+pub fn reveal(args: &RevealArgs, limits: DecodeLimits) -> Result<()> {
+    let png = Png::from_file_with_limits(&args.file_path, limits)?;
+    let (header, raw) = decode_pixels(&png)?;
+
+    let mut message = stego::extract(
+        &header,
+        &raw,
+        &layout(&args.layout),
+        &stego::derive_seed(args.key.as_bytes())?,
+    )?;
+    match &args.passphrase {
+        Some(passphrase) => message = crypto::open(passphrase.as_bytes(), &message)?,
+        None if crypto::is_sealed(&message) => {
+            eprintln!("warning: hidden message is encrypted, use --passphrase")
+        }
+        None => {}
+    }
+
+    match &args.output {
+        Some(path) => fs::write(path, message)?,
+        None => println!("{}", String::from_utf8_lossy(&message)),
+    }
+    Ok(())
+}
+
+pub fn capacity(args: &CapacityArgs, limits: DecodeLimits) -> Result<()> {
+    let png = Png::from_file_with_limits(&args.file_path, limits)?;
+    let header = png.image_header()?;
+    let layout = layout(&args.layout);
+
+    println!(
+        "{}: room for {} bytes in the {} channel(s) at bit plane(s) {:?}",
+        header,
+        stego::capacity(&header, &layout)?,
+        layout
+            .channels
+            .iter()
+            .map(|channel| channel.to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+        layout.bit_planes
+    );
+    Ok(())
+}
+
+pub fn keygen(args: &KeygenArgs) -> Result<()> {
+    let key = SigningKey::generate(&mut OsRng);
+    let public_path = format!("{}.pub", args.key_file);
+
+    write_private_key(&args.key_file, signature::to_hex(&key.to_bytes()) + "\n")?;
+    fs::write(
+        &public_path,
+        signature::to_hex(&key.verifying_key().to_bytes()) + "\n",
+    )?;
+
+    println!("wrote {} and {}", args.key_file, public_path);
+    Ok(())
+}
+
+/// Creates `path` readable by its owner only, refusing to overwrite an existing key.
+fn write_private_key(path: &str, contents: String) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("cannot create {}: {}", path, e))?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+pub fn sign(args: &SignArgs, limits: DecodeLimits) -> Result<()> {
+    let mut png = Png::from_file_with_limits(&args.file_path, limits)?;
+    let key = signature::signing_key_from_hex(&fs::read_to_string(&args.key)?)?;
+
+    let mut protected: Vec<ChunkType> = Vec::new();
+    for chunk_type in parse_chunk_types(&args.protect)? {
+        if !protected.contains(&chunk_type) {
+            protected.push(chunk_type);
+        }
+    }
+    for chunk_type in protected
+        .iter()
+        .filter(|chunk_type| signature::guards_pixels(chunk_type))
+    {
+        eprintln!(
+            "warning: {} changes the pixels, it is always signed",
+            chunk_type
+        );
+    }
+    protected.retain(|chunk_type| !signature::guards_pixels(chunk_type));
+
+    // An embedded signature replaces any earlier one.
+    if args.detached.is_none() {
+        png.remove_chunks(|chunk| chunk.chunk_type().to_string() == signature::CHUNK_TYPE);
+    }
+    let signed = Signature::sign(png.chunks(), &protected, &key)?;
+
+    match &args.detached {
+        Some(path) => {
+            fs::write(path, signed.to_bytes())?;
+            println!("wrote detached signature to {}", path);
+        }
+        None => {
+            png.insert_chunk_before_iend(signed.to_chunk());
+            fs::write(
+                args.output_file.as_ref().unwrap_or(&args.file_path),
+                png.as_bytes(),
+            )?;
+            println!("signed with key {}", signature::to_hex(&signed.public_key));
+        }
+    }
+    Ok(())
+}
+
+pub fn verify(args: &VerifyArgs, limits: DecodeLimits) -> Result<()> {
+    let png = Png::from_file_with_limits(&args.file_path, limits)?;
+    let data = match &args.signature {
+        Some(path) => fs::read(path)?,
+        None => png
+            .chunk_by_type(signature::CHUNK_TYPE)
+            .ok_or("image is not signed")?
+            .data()
+            .to_vec(),
+    };
+    let signed = Signature::from_bytes(&data)?;
+
+    let signer = signature::to_hex(&signed.public_key);
+    if let Some(path) = &args.public_key {
+        let expected = signature::public_key_from_hex(&fs::read_to_string(path)?)?;
+        if expected != signed.public_key {
+            return Err(format!("image was signed by a different key {}", signer).into());
+        }
+    }
+
+    let result = signed.verify(png.chunks())?;
+    let status = |unchanged: bool| if unchanged { "unchanged" } else { "CHANGED" };
+    println!(
+        "pixels (critical, transparency and animation chunks): {}",
+        status(result.pixels_unchanged)
+    );
+    println!(
+        "protected metadata ({}): {}",
+        if signed.protected.is_empty() {
+            "none".to_string()
+        } else {
+            signed
+                .protected
+                .iter()
+                .map(ChunkType::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        },
+        status(result.ancillary_unchanged)
+    );
+
+    if !result.is_valid() {
+        return Err("image was modified after signing".into());
+    }
+    // Anyone can sign with their own key, so without --public-key the signature only
+    // shows the image was not modified after whoever signed it.
+    match &args.public_key {
+        Some(_) => println!("signature is valid"),
+        None => println!(
+            "signature matches embedded key {}, signer not verified, use --public-key",
+            signer
+        ),
+    }
+    Ok(())
+}
+
+pub fn hash(args: &HashArgs, limits: DecodeLimits) -> Result<()> {
+    let mut hashes = Vec::with_capacity(args.files.len());
+    for path in args.files.iter() {
+        let hash = Png::open_mmap_with_limits(path, limits)
+            .and_then(|png| png.verify_crcs().and_then(|_| png.pixel_hash()))
+            .map_err(|e| format!("{}: {}", path, e))?;
+        hashes.push((signature::to_hex(&hash), path));
+    }
+
+    if args.duplicates {
+        // The sort is stable, so files with the same hash stay in the order given.
+        hashes.sort_by(|a, b| a.0.cmp(&b.0));
+        for group in hashes.chunk_by(|a, b| a.0 == b.0) {
+            if group.len() > 1 {
+                for (hash, path) in group {
+                    println!("{}  {}", hash, path);
+                }
+                println!();
+            }
+        }
+    } else {
+        for (hash, path) in hashes.iter() {
+            println!("{}  {}", hash, path);
+        }
+    }
+    Ok(())
+}
+
+pub fn inspect(args: &InspectArgs, limits: DecodeLimits) -> Result<()> {
+    let size = fs::metadata(&args.file_path)?.len();
+    limits.check_total_size(usize::try_from(size).unwrap_or(usize::MAX))?;
+    let bytes = fs::read(&args.file_path)?;
+    print!(
+        "{}: {}",
+        args.file_path,
+        inspect::inspect_with_limits(&bytes, limits)
+    );
     Ok(())
 }
 
-pub fn print(args: &PrintArgs) -> Result<()> {
-    let png: Png = fs::read(&args.file_path)?.as_slice().try_into()?;
-    println!("{}", png);
+pub fn trailer(args: &TrailerArgs, limits: DecodeLimits) -> Result<()> {
+    let mut png = Png::from_file_with_limits(&args.file_path, limits)?;
+    if png.trailer().is_empty() {
+        println!("no data after IEND");
+        return Ok(());
+    }
 
+    if let Some(path) = &args.extract {
+        fs::write(path, png.trailer())?;
+        println!("wrote {} trailing bytes to {}", png.trailer().len(), path);
+    } else if args.strip {
+        let trailer = png.take_trailer();
+        fs::write(
+            args.output_file.as_ref().unwrap_or(&args.file_path),
+            png.as_bytes(),
+        )?;
+        println!("stripped {} bytes after IEND", trailer.len());
+    } else {
+        let trailer = png.trailer();
+        println!(
+            "{} bytes after IEND ({})",
+            trailer.len(),
+            describe_trailer(trailer)
+        );
+        print!(
+            "{}",
+            hex_dump(&trailer[..trailer.len().min(TRAILER_PREVIEW)])
+        );
+        if trailer.len() > TRAILER_PREVIEW {
+            println!("... use --extract to save all of it");
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Guesses what was appended after IEND from its first bytes.
+fn describe_trailer(trailer: &[u8]) -> String {
+    if trailer.starts_with(b"PK\x03\x04") {
+        return "ZIP archive".to_string();
+    }
+    if trailer.iter().all(|&byte| byte == 0) {
+        return "zero padding".to_string();
+    }
+
+    // Files written by older versions of `encode` put chunks after IEND.
+    let mut reader = BufReader::new(trailer);
+    let mut chunks = Vec::new();
+    while !reader.fill_buf().unwrap().is_empty() {
+        match Chunk::read_chunk(&mut reader, &DecodeLimits::default()) {
+            Ok(chunk) => chunks.push(chunk.chunk_type().to_string()),
+            Err(_) => return "unknown data".to_string(),
+        }
+    }
+    format!("chunks {}", chunks.join(", "))
+}
+
+pub fn repair(args: &RepairArgs, limits: DecodeLimits) -> Result<()> {
+    let bytes = fs::read(&args.file_path)?;
+    let mut recovered = recover::parse_lenient(&bytes, limits);
+    for problem in recovered.problems.iter() {
+        println!("found: {}", problem);
+    }
+
+    let changes = recover::repair(&mut recovered.png)?;
+    for change in changes.iter() {
+        println!("fixed: {}", change);
+    }
+
+    if recovered.problems.is_empty() && changes.is_empty() {
+        println!("nothing to repair");
+        return Ok(());
+    }
+    fs::write(
+        args.output_file.as_ref().unwrap_or(&args.file_path),
+        recovered.png.as_bytes(),
+    )?;
+    Ok(())
+}
+
+fn layout(args: &LayoutArgs) -> Layout {
+    Layout {
+        channels: args.channels.clone(),
+        bit_planes: args.bits.clone(),
+    }
+}
+
+/// Decodes the default image into non-interlaced scanlines without filter bytes.
+fn decode_pixels(png: &Png) -> Result<(ImageHeader, Vec<u8>)> {
+    let header = png.image_header()?;
+    let raw = image::decode_pixels(&header, &png.idat_data(), png.limits())?;
+    Ok((header, raw))
+}
+
+fn preset_chunk_types(preset: StripPreset) -> Vec<&'static str> {
+    match preset {
+        StripPreset::Privacy => PRIVACY_CHUNKS.to_vec(),
+        StripPreset::Web => [&PRIVACY_CHUNKS[..], &WEB_COLOR_CHUNKS[..]].concat(),
+    }
+}
+
+fn parse_chunk_types<S: AsRef<str>>(names: &[S]) -> Result<Vec<ChunkType>> {
+    names
+        .iter()
+        .map(|name| Ok(ChunkType::from_str(name.as_ref())?))
+        .collect()
+}
+
+/// Like `parse_chunk_types`, but accepts any type `print` can list, such as types
+/// with the reserved bit set, so every chunk in a file can be selected.
+fn parse_chunk_selectors(names: &[String]) -> Result<Vec<ChunkType>> {
+    names
+        .iter()
+        .map(|name| Ok(ChunkType::from_str_lenient(name)?))
+        .collect()
+}
+
+/// Formats `data` as lines of 16 bytes with an offset, hex and ASCII column.
+fn hex_dump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, bytes) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let ascii: String = bytes
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        dump.push_str(&format!(
+            "{:08x}  {:<47}  |{}|\n",
+            line * 16,
+            hex.join(" "),
+            ascii
+        ));
+    }
+    dump
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keygen_private_key_is_owner_only() {
+        let key_file = std::env::temp_dir()
+            .join(format!("png-rs-{}-keygen", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let args = KeygenArgs {
+            key_file: key_file.clone(),
+        };
+
+        keygen(&args).unwrap();
+        let private_key = fs::read_to_string(&key_file).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&key_file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // An existing key is never overwritten.
+        assert!(keygen(&args).is_err());
+        assert_eq!(fs::read_to_string(&key_file).unwrap(), private_key);
+
+        fs::remove_file(&key_file).unwrap();
+        fs::remove_file(format!("{}.pub", key_file)).unwrap();
+    }
+
+    /// Runs `strip` on a file holding one chunk of each type in `ancillary` and
+    /// returns the chunk types left.
+    fn strip_chunk_types(name: &str, ancillary: &[&str], args: StripArgs) -> Vec<String> {
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/rgb8-n-sub.png");
+        let mut png = Png::from_file(source).unwrap();
+        for chunk_type in ancillary {
+            png.insert_chunk_before_iend(Chunk::new(
+                ChunkType::from_str(chunk_type).unwrap(),
+                Vec::new(),
+            ));
+        }
+        let path = std::env::temp_dir()
+            .join(format!("png-rs-{}-strip-{}.png", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned();
+        fs::write(&path, png.as_bytes()).unwrap();
+
+        strip(
+            &StripArgs {
+                file_path: path.clone(),
+                ..args
+            },
+            DecodeLimits::default(),
+        )
+        .unwrap();
+        let stripped = Png::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        stripped
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    fn strip_args(keep: &[&str], drop: &[&str], preset: Option<StripPreset>) -> StripArgs {
+        StripArgs {
+            file_path: String::new(),
+            output_file: None,
+            keep: keep.iter().map(|name| name.to_string()).collect(),
+            drop: drop.iter().map(|name| name.to_string()).collect(),
+            preset,
+        }
+    }
+
+    fn decode_args(file_path: &str, format: DecodeFormat) -> DecodeArgs {
+        DecodeArgs {
+            file_path: file_path.to_string(),
+            chunk_type: "ruSt".to_string(),
+            all: true,
+            format,
+            output: None,
+            output_dir: None,
+            passphrase: None,
+            no_decompress: false,
+        }
+    }
+
+    #[test]
+    fn test_decode_all_keeps_messages_apart() {
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/rgb8-n-sub.png");
+        let mut png = Png::from_file(source).unwrap();
+        for message in ["first", "second"] {
+            png.insert_chunk_before_iend(Chunk::new(
+                ChunkType::from_str("ruSt").unwrap(),
+                message.as_bytes().to_vec(),
+            ));
+        }
+        let base = std::env::temp_dir().join(format!("png-rs-{}-decode-all", std::process::id()));
+        let file_path = base.with_extension("png").to_string_lossy().into_owned();
+        fs::write(&file_path, png.as_bytes()).unwrap();
+
+        let raw = decode(
+            &decode_args(&file_path, DecodeFormat::Raw),
+            DecodeLimits::default(),
+        );
+        assert!(raw.unwrap_err().to_string().contains("--output-dir"));
+
+        let hex_path = base.with_extension("hex").to_string_lossy().into_owned();
+        let hex = DecodeArgs {
+            output: Some(hex_path.clone()),
+            ..decode_args(&file_path, DecodeFormat::Hex)
+        };
+        decode(&hex, DecodeLimits::default()).unwrap();
+        let dump = fs::read_to_string(&hex_path).unwrap();
+        assert!(dump.starts_with("ruSt message 0, 5 bytes:\n00000000"));
+        assert!(dump.contains("ruSt message 1, 6 bytes:\n00000000"));
+
+        let output_dir = base.to_string_lossy().into_owned();
+        let separate = DecodeArgs {
+            output_dir: Some(output_dir.clone()),
+            ..decode_args(&file_path, DecodeFormat::Raw)
+        };
+        decode(&separate, DecodeLimits::default()).unwrap();
+        assert_eq!(fs::read(base.join("ruSt-000.bin")).unwrap(), b"first");
+        assert_eq!(fs::read(base.join("ruSt-001.bin")).unwrap(), b"second");
+
+        fs::remove_dir_all(&output_dir).unwrap();
+        fs::remove_file(&hex_path).unwrap();
+        fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_decompresses_unless_told_not_to() {
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/rgb8-n-sub.png");
+        let mut png = Png::from_file(source).unwrap();
+        png.insert_chunk_before_iend(Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            payload::compress(b"compressed message"),
+        ));
+        let base = std::env::temp_dir().join(format!("png-rs-{}-decompress", std::process::id()));
+        let file_path = base.with_extension("png").to_string_lossy().into_owned();
+        let output = base.with_extension("bin").to_string_lossy().into_owned();
+        fs::write(&file_path, png.as_bytes()).unwrap();
+
+        let args = DecodeArgs {
+            output: Some(output.clone()),
+            ..decode_args(&file_path, DecodeFormat::Raw)
+        };
+        decode(&args, DecodeLimits::default()).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"compressed message");
+
+        let stored = DecodeArgs {
+            no_decompress: true,
+            ..args
+        };
+        decode(&stored, DecodeLimits::default()).unwrap();
+        assert_eq!(
+            fs::read(&output).unwrap(),
+            payload::compress(b"compressed message")
+        );
+
+        fs::remove_file(&output).unwrap();
+        fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_finds_chunks_after_iend() {
+        // What the first version of `encode` wrote: the message chunk after IEND.
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/rgb8-n-sub.png");
+        let message = Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            b"old message".to_vec(),
+        );
+        let bytes = [fs::read(source).unwrap(), message.as_bytes()].concat();
+
+        let base = std::env::temp_dir().join(format!("png-rs-{}-after-iend", std::process::id()));
+        let file_path = base.with_extension("png").to_string_lossy().into_owned();
+        let output = base.with_extension("bin").to_string_lossy().into_owned();
+        fs::write(&file_path, bytes).unwrap();
+
+        let args = DecodeArgs {
+            all: false,
+            output: Some(output.clone()),
+            ..decode_args(&file_path, DecodeFormat::Raw)
+        };
+        decode(&args, DecodeLimits::default()).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"old message");
+
+        let missing = DecodeArgs {
+            chunk_type: "riSt".to_string(),
+            ..args
+        };
+        assert!(decode(&missing, DecodeLimits::default()).is_err());
+
+        fs::remove_file(&output).unwrap();
+        fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_strip_selects_reserved_bit_types() {
+        // "ruse" has the reserved bit set, so only the lenient parser reads it.
+        assert!(ChunkType::from_str("ruse").is_err());
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/rgb8-n-sub.png");
+        let reserved = Chunk::new(ChunkType::from_str_lenient("ruse").unwrap(), Vec::new());
+        let mut png = Png::from_file(source).unwrap();
+        png.insert_chunk_before_iend(reserved);
+        let path = std::env::temp_dir()
+            .join(format!("png-rs-{}-strip-reserved.png", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        fs::write(&path, png.as_bytes()).unwrap();
+
+        let args = StripArgs {
+            file_path: path.clone(),
+            ..strip_args(&[], &["ruse"], None)
+        };
+        strip(&args, DecodeLimits::default()).unwrap();
+        let stripped = Png::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(stripped.chunk_by_type("ruse").is_none());
+    }
+
+    const ANCILLARY: [&str; 6] = ["tEXt", "tIME", "sRGB", "gAMA", "iCCP", "ruSt"];
+
+    #[test]
+    fn test_strip_everything_but_keep() {
+        assert_eq!(
+            strip_chunk_types("all", &ANCILLARY, strip_args(&[], &[], None)),
+            ["IHDR", "IDAT", "IEND"]
+        );
+        assert_eq!(
+            strip_chunk_types("keep", &ANCILLARY, strip_args(&["sRGB", "ruSt"], &[], None)),
+            ["IHDR", "IDAT", "sRGB", "ruSt", "IEND"]
+        );
+    }
+
+    #[test]
+    fn test_strip_drop_list() {
+        assert_eq!(
+            strip_chunk_types(
+                "drop",
+                &ANCILLARY,
+                strip_args(&[], &["tEXt", "ruSt", "IDAT"], None)
+            ),
+            ["IHDR", "IDAT", "tIME", "sRGB", "gAMA", "iCCP", "IEND"]
+        );
+        assert_eq!(
+            strip_chunk_types(
+                "drop-keep",
+                &ANCILLARY,
+                strip_args(&["tEXt"], &["tEXt", "tIME"], None)
+            ),
+            ["IHDR", "IDAT", "tEXt", "sRGB", "gAMA", "iCCP", "ruSt", "IEND"]
+        );
+    }
+
+    #[test]
+    fn test_strip_presets() {
+        assert_eq!(
+            strip_chunk_types(
+                "privacy",
+                &ANCILLARY,
+                strip_args(&[], &[], Some(StripPreset::Privacy))
+            ),
+            ["IHDR", "IDAT", "sRGB", "gAMA", "iCCP", "ruSt", "IEND"]
+        );
+        assert_eq!(
+            strip_chunk_types(
+                "web",
+                &ANCILLARY,
+                strip_args(&[], &[], Some(StripPreset::Web))
+            ),
+            ["IHDR", "IDAT", "sRGB", "gAMA", "ruSt", "IEND"]
+        );
+        assert_eq!(
+            strip_chunk_types(
+                "web-drop",
+                &ANCILLARY,
+                strip_args(&[], &["ruSt"], Some(StripPreset::Web))
+            ),
+            ["IHDR", "IDAT", "sRGB", "gAMA", "IEND"]
+        );
+    }
+}
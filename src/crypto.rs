@@ -0,0 +1,223 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+/// Identifies chunk data sealed by `seal`.
+pub const MAGIC: [u8; 4] = *b"pngE";
+/// The only header layout understood by `open`.
+pub const VERSION: u8 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+/// magic, version, three u32 Argon2 parameters, salt and nonce.
+const HEADER_LEN: usize = 4 + 1 + 12 + SALT_LEN + NONCE_LEN;
+
+/// Upper bounds on the Argon2 parameters accepted from a header, so a crafted chunk
+/// cannot make `open` allocate gigabytes or spin forever.
+const MAX_MEMORY_KIB: u32 = 1024 * 1024;
+const MAX_ITERATIONS: u32 = 64;
+const MAX_PARALLELISM: u32 = 16;
+
+/// Argon2id cost parameters used to derive the encryption key from a passphrase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Runs Argon2id over `passphrase` and `salt`, refusing parameters above the caps.
+    pub fn derive_key(&self, passphrase: &[u8], salt: &[u8]) -> Result<[u8; KEY_LEN], String> {
+        if self.memory_kib > MAX_MEMORY_KIB
+            || self.iterations > MAX_ITERATIONS
+            || self.parallelism > MAX_PARALLELISM
+        {
+            return Err(format!(
+                "key derivation parameters too expensive: {:?}",
+                self
+            ));
+        }
+
+        let params = Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            Some(KEY_LEN),
+        )
+        .map_err(|e| format!("invalid key derivation parameters: {}", e))?;
+
+        let mut key = [0; KEY_LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase, salt, &mut key)
+            .map_err(|e| format!("key derivation failed: {}", e))?;
+        Ok(key)
+    }
+}
+
+/// True if `data` starts with the header written by `seal`.
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Encrypts `plaintext` with a key derived from `passphrase` using the default
+/// Argon2id parameters.
+pub fn seal(passphrase: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    seal_with_params(passphrase, plaintext, KdfParams::default())
+}
+
+/// Encrypts `plaintext` with XChaCha20-Poly1305. The output is a versioned header
+/// holding the KDF parameters, salt and nonce, followed by the ciphertext. The
+/// header is authenticated along with the ciphertext.
+pub fn seal_with_params(
+    passphrase: &[u8],
+    plaintext: &[u8],
+    params: KdfParams,
+) -> Result<Vec<u8>, String> {
+    let mut salt = [0; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(&MAGIC);
+    header.push(VERSION);
+    header.extend_from_slice(&params.memory_kib.to_be_bytes());
+    header.extend_from_slice(&params.iterations.to_be_bytes());
+    header.extend_from_slice(&params.parallelism.to_be_bytes());
+    header.extend_from_slice(&salt);
+    header.extend_from_slice(&nonce);
+
+    let key = params.derive_key(passphrase, &salt)?;
+    let ciphertext = XChaCha20Poly1305::new(&key.into())
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: &header,
+            },
+        )
+        .map_err(|_| "encryption failed".to_string())?;
+
+    Ok([header, ciphertext].concat())
+}
+
+/// Decrypts data produced by `seal`, failing if the passphrase is wrong or any byte
+/// of the header or ciphertext was modified.
+pub fn open(passphrase: &[u8], sealed: &[u8]) -> Result<Vec<u8>, String> {
+    if !is_sealed(sealed) {
+        return Err("data is not encrypted".to_string());
+    }
+    if sealed.len() < HEADER_LEN {
+        return Err("encrypted data is truncated".to_string());
+    }
+    if sealed[4] != VERSION {
+        return Err(format!("unsupported encryption version {}", sealed[4]));
+    }
+
+    let be_u32 = |offset: usize| {
+        u32::from_be_bytes([
+            sealed[offset],
+            sealed[offset + 1],
+            sealed[offset + 2],
+            sealed[offset + 3],
+        ])
+    };
+    let params = KdfParams {
+        memory_kib: be_u32(5),
+        iterations: be_u32(9),
+        parallelism: be_u32(13),
+    };
+    let (header, ciphertext) = sealed.split_at(HEADER_LEN);
+    let salt = &header[17..17 + SALT_LEN];
+    let nonce = XNonce::from_slice(&header[17 + SALT_LEN..]);
+
+    let key = params.derive_key(passphrase, salt)?;
+    XChaCha20Poly1305::new(&key.into())
+        .decrypt(
+            nonce,
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map_err(|_| "decryption failed: wrong passphrase or tampered data".to_string())
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap parameters keep the tests fast; `open` reads them back from the header.
+    const TEST_PARAMS: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn test_seal_open_round_trip() {
+        let message = b"This is where your secret message will be!";
+
+        let sealed = seal_with_params(b"hunter2", message, TEST_PARAMS).unwrap();
+
+        assert!(is_sealed(&sealed));
+        assert!(!sealed
+            .windows(message.len())
+            .any(|window| window == message));
+        assert_eq!(open(b"hunter2", &sealed).unwrap(), message);
+    }
+
+    #[test]
+    fn test_open_wrong_passphrase() {
+        let sealed = seal_with_params(b"hunter2", b"secret", TEST_PARAMS).unwrap();
+
+        let error = open(b"hunter3", &sealed).unwrap_err();
+        assert!(error.contains("wrong passphrase"));
+    }
+
+    #[test]
+    fn test_open_detects_tampering() {
+        let sealed = seal_with_params(b"hunter2", b"secret", TEST_PARAMS).unwrap();
+
+        // Flip a bit in the ciphertext, then in the authenticated salt.
+        for index in [sealed.len() - 1, 20] {
+            let mut tampered = sealed.clone();
+            tampered[index] ^= 1;
+            assert!(open(b"hunter2", &tampered).is_err());
+        }
+    }
+
+    #[test]
+    fn test_open_rejects_bad_headers() {
+        let sealed = seal_with_params(b"hunter2", b"secret", TEST_PARAMS).unwrap();
+
+        assert!(open(b"hunter2", b"plain text").is_err());
+        assert!(open(b"hunter2", &sealed[..20]).is_err());
+
+        let mut future_version = sealed.clone();
+        future_version[4] = 2;
+        assert!(open(b"hunter2", &future_version)
+            .unwrap_err()
+            .contains("unsupported encryption version"));
+
+        let mut expensive = sealed;
+        expensive[5..9].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(open(b"hunter2", &expensive)
+            .unwrap_err()
+            .contains("too expensive"));
+    }
+}
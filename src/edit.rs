@@ -0,0 +1,307 @@
+use crate::chunk::Chunk;
+use crate::limits::DecodeLimits;
+use crate::mapped::MappedPng;
+use crate::png::Png;
+
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Most bytes `apply_in_place` reads and rewrites by default. Edits further from the
+/// end are left to a full rewrite.
+pub const DEFAULT_MAX_TAIL: usize = 16 << 20;
+
+/// Chunks to remove from and insert into a file, written by `apply_in_place` without
+/// touching anything before the first affected chunk.
+#[derive(Debug, Default)]
+pub struct TailEdit {
+    /// Indices of the chunks to remove, as shown by `print`. Only ancillary chunks
+    /// can be removed in place.
+    pub remove: Vec<usize>,
+    /// Chunks to insert right before IEND, like `Png::insert_chunk_before_iend`.
+    pub insert_before_iend: Vec<Chunk>,
+}
+
+/// A byte range of a file and what replaces it.
+#[derive(Debug, PartialEq)]
+pub struct Splice {
+    pub offset: usize,
+    /// Number of bytes replaced, always the rest of the file.
+    pub old_len: usize,
+    pub data: Vec<u8>,
+}
+
+/// Works out the bytes `edit` changes in the mapped file. Returns `None` if the edit
+/// removes a critical chunk or more than `max_tail` bytes would have to be rewritten.
+/// The CRCs of the chunks that are rewritten are checked, the rest are not read.
+pub fn plan(png: &MappedPng, edit: &TailEdit, max_tail: usize) -> Result<Option<Splice>, String> {
+    let index = png.index();
+    for &removed in edit.remove.iter() {
+        let entry = index.get(removed).ok_or_else(|| {
+            format!(
+                "chunk index {} out of range, png has {} chunks",
+                removed,
+                index.len()
+            )
+        })?;
+        if entry.chunk_type.is_critical() {
+            return Ok(None);
+        }
+    }
+
+    let bytes = png.as_bytes();
+    let trailer_start = bytes.len() - png.trailer().len();
+    let insert_at = index
+        .iter()
+        .position(|entry| entry.chunk_type.to_string() == "IEND")
+        .unwrap_or(index.len());
+    let first = edit
+        .remove
+        .iter()
+        .copied()
+        .chain((!edit.insert_before_iend.is_empty()).then_some(insert_at))
+        .min();
+    let offset = match first {
+        Some(first) => index.get(first).map_or(trailer_start, |entry| entry.offset),
+        None => bytes.len(),
+    };
+    if bytes.len() - offset > max_tail {
+        return Ok(None);
+    }
+
+    let mut data = Vec::new();
+    for (position, entry) in index.iter().enumerate() {
+        if position == insert_at {
+            for chunk in edit.insert_before_iend.iter() {
+                data.extend_from_slice(&chunk.as_bytes());
+            }
+        }
+        if entry.offset < offset || edit.remove.contains(&position) {
+            continue;
+        }
+        let chunk = png.chunk(entry)?;
+        chunk.check_crc()?;
+        data.extend_from_slice(chunk.raw());
+    }
+    if insert_at == index.len() {
+        for chunk in edit.insert_before_iend.iter() {
+            data.extend_from_slice(&chunk.as_bytes());
+        }
+    }
+    if offset <= trailer_start {
+        data.extend_from_slice(png.trailer());
+    }
+
+    Ok(Some(Splice {
+        offset,
+        old_len: bytes.len() - offset,
+        data,
+    }))
+}
+
+/// Applies `edit` to the file at `path` by rewriting only its tail, from the first
+/// removed chunk or IEND onwards. Returns `None`, leaving the file untouched, if the
+/// edit cannot be done this way (see `plan`) and the whole file has to be rewritten.
+///
+/// Only the chunks that are rewritten have their CRC checked, reading the rest would
+/// defeat the point on a multi-gigabyte file. So unlike a full rewrite, which refuses
+/// any damaged file, a bad CRC before the splice offset is left as it is. The write
+/// is not atomic: the new tail is written over the old one, the file truncated and
+/// synced, and a crash before the sync can leave the tail damaged. Everything before
+/// the splice offset is never written.
+pub fn apply_in_place<P: AsRef<Path>>(
+    path: P,
+    edit: &TailEdit,
+    max_tail: usize,
+    limits: DecodeLimits,
+) -> Result<Option<Splice>, String> {
+    // Plan with the file mapped, but unmap it before writing.
+    let splice = {
+        let png = Png::open_mmap_with_limits(&path, limits)?;
+        match plan(&png, edit, max_tail)? {
+            Some(splice) => splice,
+            None => return Ok(None),
+        }
+    };
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(|e| e.to_string())?;
+    file.seek(SeekFrom::Start(splice.offset as u64))
+        .and_then(|_| file.write_all(&splice.data))
+        .and_then(|_| file.set_len((splice.offset + splice.data.len()) as u64))
+        .and_then(|_| file.sync_all())
+        .map_err(|e| e.to_string())?;
+    Ok(Some(splice))
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    fn text_chunk(text: &str) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str("tEXt").unwrap(),
+            text.as_bytes().to_vec(),
+        )
+    }
+
+    /// A copy of a conformance image with a few ancillary chunks and a trailer.
+    fn testing_file(name: &str) -> PathBuf {
+        let source =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/rgb8-n-sub.png");
+        let mut png = Png::from_file(source).unwrap();
+        png.insert_chunk_before_iend(text_chunk("Comment\0first"));
+        png.insert_chunk_before_iend(text_chunk("Comment\0second"));
+        png.set_trailer(b"trailer".to_vec());
+
+        let path = std::env::temp_dir().join(format!("png-rs-{}-{}.png", std::process::id(), name));
+        fs::write(&path, png.as_bytes()).unwrap();
+        path
+    }
+
+    /// What `commands::encode` and `commands::remove` write without in-place editing.
+    fn full_rewrite(path: &Path, edit: TailEdit) -> Vec<u8> {
+        let mut png = Png::from_file(path).unwrap();
+        let mut remove = edit.remove;
+        remove.sort_unstable();
+        for index in remove.into_iter().rev() {
+            png.remove_chunk_at(index).unwrap();
+        }
+        for chunk in edit.insert_before_iend {
+            png.insert_chunk_before_iend(chunk);
+        }
+        png.as_bytes()
+    }
+
+    fn assert_matches_full_rewrite(name: &str, edit: impl Fn() -> TailEdit) {
+        let path = testing_file(name);
+        let original = fs::read(&path).unwrap();
+        let expected = full_rewrite(&path, edit());
+
+        let splice = apply_in_place(&path, &edit(), DEFAULT_MAX_TAIL, DecodeLimits::default())
+            .unwrap()
+            .unwrap();
+        let actual = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(actual, expected);
+        assert_eq!(actual[..splice.offset], original[..splice.offset]);
+        assert_eq!(splice.old_len, original.len() - splice.offset);
+    }
+
+    #[test]
+    fn test_insert_matches_full_rewrite() {
+        assert_matches_full_rewrite("insert", || TailEdit {
+            insert_before_iend: vec![text_chunk("Comment\0new"), text_chunk("Author\0me")],
+            ..TailEdit::default()
+        });
+    }
+
+    #[test]
+    fn test_remove_matches_full_rewrite() {
+        // IHDR, IDAT, tEXt, tEXt, IEND.
+        assert_matches_full_rewrite("remove", || TailEdit {
+            remove: vec![3, 2],
+            ..TailEdit::default()
+        });
+        assert_matches_full_rewrite("remove-and-insert", || TailEdit {
+            remove: vec![3],
+            insert_before_iend: vec![text_chunk("Comment\0new")],
+        });
+    }
+
+    #[test]
+    fn test_only_the_tail_is_rewritten() {
+        let path = testing_file("tail");
+        let png = Png::open_mmap(&path).unwrap();
+        let edit = TailEdit {
+            insert_before_iend: vec![text_chunk("Comment\0new")],
+            ..TailEdit::default()
+        };
+
+        let splice = plan(&png, &edit, DEFAULT_MAX_TAIL).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(splice.offset, png.index()[4].offset);
+        assert_eq!(splice.old_len, 12 + b"trailer".len());
+        assert!(plan(&png, &edit, splice.old_len - 1).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_only_rewritten_crcs_are_checked() {
+        let edit = || TailEdit {
+            insert_before_iend: vec![text_chunk("Comment\0new")],
+            ..TailEdit::default()
+        };
+        // Flips the first byte after the header of chunk `index`, returning the file.
+        let corrupt = |path: &Path, index: usize| {
+            let offset = Png::open_mmap(path).unwrap().index()[index]
+                .data_range()
+                .start;
+            let mut bytes = fs::read(path).unwrap();
+            bytes[offset] ^= 1;
+            fs::write(path, &bytes).unwrap();
+            bytes
+        };
+
+        // A bad CRC in the tail is refused, leaving the file as it was. IEND has no
+        // data, so this flips a byte of its CRC.
+        let path = testing_file("bad-crc-tail");
+        let bytes = corrupt(&path, 4);
+        let in_place = apply_in_place(&path, &edit(), DEFAULT_MAX_TAIL, DecodeLimits::default());
+        let after = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(in_place.unwrap_err().contains("crc"));
+        assert_eq!(after, bytes);
+
+        // Before the splice offset nothing is read, so the damage is kept, where a
+        // full rewrite would refuse the file.
+        let path = testing_file("bad-crc-idat");
+        let bytes = corrupt(&path, 1);
+        assert!(Png::from_file(&path).is_err());
+        let splice = apply_in_place(&path, &edit(), DEFAULT_MAX_TAIL, DecodeLimits::default())
+            .unwrap()
+            .unwrap();
+        let after = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(after[..splice.offset], bytes[..splice.offset]);
+    }
+
+    #[test]
+    fn test_critical_chunks_need_a_full_rewrite() {
+        let path = testing_file("critical");
+        let png = Png::open_mmap(&path).unwrap();
+        let original = fs::read(&path).unwrap();
+
+        let remove_idat = TailEdit {
+            remove: vec![1],
+            ..TailEdit::default()
+        };
+        assert!(plan(&png, &remove_idat, usize::MAX).unwrap().is_none());
+        assert!(
+            apply_in_place(&path, &remove_idat, usize::MAX, DecodeLimits::default())
+                .unwrap()
+                .is_none()
+        );
+        assert!(plan(
+            &png,
+            &TailEdit {
+                remove: vec![9],
+                ..TailEdit::default()
+            },
+            usize::MAX
+        )
+        .is_err());
+
+        assert_eq!(fs::read(&path).unwrap(), original);
+        fs::remove_file(&path).unwrap();
+    }
+}
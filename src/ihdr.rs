@@ -0,0 +1,195 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The color type field of the IHDR chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale = 0,
+    Rgb = 2,
+    Indexed = 3,
+    GrayscaleAlpha = 4,
+    Rgba = 6,
+}
+
+impl ColorType {
+    /// Number of samples stored per pixel.
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    /// Bit depths the spec allows for this color type.
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        }
+    }
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(format!("invalid color type {}", value)),
+        }
+    }
+}
+
+/// The decoded contents of an IHDR chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub interlaced: bool,
+}
+
+impl ImageHeader {
+    pub const LENGTH: usize = 13;
+
+    /// Parses and validates the 13 data bytes of an IHDR chunk.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.len() != Self::LENGTH {
+            return Err(format!(
+                "IHDR must be {} bytes, found {}",
+                Self::LENGTH,
+                data.len()
+            ));
+        }
+
+        let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        if width == 0 || height == 0 || width > i32::MAX as u32 || height > i32::MAX as u32 {
+            return Err(format!("invalid image dimensions {}x{}", width, height));
+        }
+
+        let bit_depth = data[8];
+        let color_type = ColorType::try_from(data[9])?;
+        if !color_type.allowed_bit_depths().contains(&bit_depth) {
+            return Err(format!(
+                "bit depth {} is not allowed for color type {:?}",
+                bit_depth, color_type
+            ));
+        }
+
+        if data[10] != 0 {
+            return Err(format!("unknown compression method {}", data[10]));
+        }
+        if data[11] != 0 {
+            return Err(format!("unknown filter method {}", data[11]));
+        }
+        let interlaced = match data[12] {
+            0 => false,
+            1 => true,
+            method => return Err(format!("unknown interlace method {}", method)),
+        };
+
+        Ok(ImageHeader {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced,
+        })
+    }
+
+    /// Serializes this header as the 13 data bytes of an IHDR chunk.
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::LENGTH);
+        bytes.extend_from_slice(&self.width.to_be_bytes());
+        bytes.extend_from_slice(&self.height.to_be_bytes());
+        bytes.push(self.bit_depth);
+        bytes.push(self.color_type as u8);
+        bytes.push(0);
+        bytes.push(0);
+        bytes.push(self.interlaced as u8);
+        bytes
+    }
+
+    /// Builds the IHDR `Chunk` for this header.
+    pub fn to_chunk(self) -> Chunk {
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), self.to_bytes())
+    }
+
+    /// Number of bits used by a single pixel.
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * self.bit_depth as usize
+    }
+}
+
+impl fmt::Display for ImageHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{} {:?} {}-bit{}",
+            self.width,
+            self.height,
+            self.color_type,
+            self.bit_depth,
+            if self.interlaced { " interlaced" } else { "" }
+        )
+    }
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = ImageHeader {
+            width: 50,
+            height: 40,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        };
+
+        let parsed = ImageHeader::from_bytes(&header.to_bytes()).unwrap();
+
+        assert_eq!(parsed, header);
+        assert_eq!(parsed.bits_per_pixel(), 32);
+    }
+
+    #[test]
+    fn test_header_rejects_invalid_fields() {
+        let valid = [0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0];
+        assert!(ImageHeader::from_bytes(&valid).is_ok());
+
+        let mut zero_width = valid;
+        zero_width[3] = 0;
+        assert!(ImageHeader::from_bytes(&zero_width).is_err());
+
+        let mut bad_depth = valid;
+        bad_depth[8] = 4;
+        assert!(ImageHeader::from_bytes(&bad_depth).is_err());
+
+        let mut bad_color = valid;
+        bad_color[9] = 5;
+        assert!(ImageHeader::from_bytes(&bad_color).is_err());
+
+        let mut bad_interlace = valid;
+        bad_interlace[12] = 2;
+        assert!(ImageHeader::from_bytes(&bad_interlace).is_err());
+
+        assert!(ImageHeader::from_bytes(&valid[..12]).is_err());
+    }
+}
@@ -0,0 +1,871 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::{ColorType, ImageHeader};
+use crate::limits::DecodeLimits;
+use crate::png::Png;
+use crate::zlib;
+
+use std::io::{self, Read};
+use std::str::FromStr;
+
+/// Adam7 passes as (x_start, y_start, x_step, y_step).
+const ADAM7_PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Number of bytes in one unfiltered scanline of `width` pixels.
+pub fn scanline_len(header: &ImageHeader, width: usize) -> usize {
+    (width * header.bits_per_pixel()).div_ceil(8)
+}
+
+/// Distance in bytes to the corresponding byte of the previous pixel, as used by the filters.
+fn filter_unit(header: &ImageHeader) -> usize {
+    (header.bits_per_pixel() / 8).max(1)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Reverses the scanline filters of `height` rows of `1 + stride` bytes at the start
+/// of `data`. Returns the unfiltered rows and the number of bytes consumed.
+fn unfilter(
+    data: &[u8],
+    stride: usize,
+    height: usize,
+    unit: usize,
+) -> Result<(Vec<u8>, usize), String> {
+    let row_len = stride + 1;
+    let needed = row_len
+        .checked_mul(height)
+        .ok_or("image dimensions overflow")?;
+    if data.len() < needed {
+        return Err(format!(
+            "image data is truncated, expected {} bytes but found {}",
+            needed,
+            data.len()
+        ));
+    }
+
+    let mut output = vec![0; stride * height];
+    for y in 0..height {
+        let (previous, current) = output.split_at_mut(y * stride);
+        let previous = if y > 0 {
+            Some(&previous[(y - 1) * stride..])
+        } else {
+            None
+        };
+        unfilter_row(
+            &data[y * row_len..(y + 1) * row_len],
+            previous,
+            &mut current[..stride],
+            unit,
+            y,
+        )?;
+    }
+
+    Ok((output, needed))
+}
+
+/// Reverses the filter of one scanline, `line` being the filter type byte followed by
+/// the filtered bytes. `previous` is the unfiltered row above, if any.
+fn unfilter_row(
+    line: &[u8],
+    previous: Option<&[u8]>,
+    current: &mut [u8],
+    unit: usize,
+    y: usize,
+) -> Result<(), String> {
+    let (filter, line) = (line[0], &line[1..]);
+    for x in 0..current.len() {
+        let a = if x >= unit { current[x - unit] } else { 0 };
+        let b = previous.map_or(0, |row| row[x]);
+        let c = match previous {
+            Some(row) if x >= unit => row[x - unit],
+            _ => 0,
+        };
+        current[x] = match filter {
+            0 => line[x],
+            1 => line[x].wrapping_add(a),
+            2 => line[x].wrapping_add(b),
+            3 => line[x].wrapping_add(((a as u16 + b as u16) / 2) as u8),
+            4 => line[x].wrapping_add(paeth(a, b, c)),
+            filter => return Err(format!("invalid filter type {} on row {}", filter, y)),
+        };
+    }
+    Ok(())
+}
+
+/// Applies filter type `filter` to one scanline, appending the filter byte and result to `output`.
+fn filter_row(
+    filter: u8,
+    current: &[u8],
+    previous: Option<&[u8]>,
+    unit: usize,
+    output: &mut Vec<u8>,
+) {
+    output.push(filter);
+    for x in 0..current.len() {
+        let a = if x >= unit { current[x - unit] } else { 0 };
+        let b = previous.map_or(0, |row| row[x]);
+        let c = match previous {
+            Some(row) if x >= unit => row[x - unit],
+            _ => 0,
+        };
+        output.push(match filter {
+            0 => current[x],
+            1 => current[x].wrapping_sub(a),
+            2 => current[x].wrapping_sub(b),
+            3 => current[x].wrapping_sub(((a as u16 + b as u16) / 2) as u8),
+            _ => current[x].wrapping_sub(paeth(a, b, c)),
+        });
+    }
+}
+
+/// Filters `raw` as rows of `stride` bytes. Every row gets filter type `filter`, or
+/// with `None` the filter type with the smallest sum of absolute differences.
+fn filter_rows(raw: &[u8], stride: usize, unit: usize, filter: Option<u8>) -> Vec<u8> {
+    let mut output = Vec::with_capacity(raw.len() + raw.len() / stride);
+    let mut candidate = Vec::with_capacity(stride + 1);
+
+    for (y, current) in raw.chunks(stride).enumerate() {
+        let previous = if y > 0 {
+            Some(&raw[(y - 1) * stride..y * stride])
+        } else {
+            None
+        };
+
+        if let Some(filter) = filter {
+            filter_row(filter, current, previous, unit, &mut output);
+            continue;
+        }
+
+        let mut best: Option<(u64, Vec<u8>)> = None;
+        for filter in 0..5 {
+            candidate.clear();
+            filter_row(filter, current, previous, unit, &mut candidate);
+            let score = candidate[1..]
+                .iter()
+                .map(|&byte| (byte as i8).unsigned_abs() as u64)
+                .sum();
+            if best
+                .as_ref()
+                .is_none_or(|(best_score, _)| score < *best_score)
+            {
+                best = Some((score, candidate.clone()));
+            }
+        }
+        output.extend_from_slice(&best.expect("five filters were tried").1);
+    }
+
+    output
+}
+
+/// Filters non-interlaced scanlines, splitting them into Adam7 passes first if
+/// `header` is interlaced. See `filter_rows` for `filter`.
+fn filter_image(header: &ImageHeader, raw: &[u8], filter: Option<u8>) -> Vec<u8> {
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let stride = scanline_len(header, width);
+    let unit = filter_unit(header);
+
+    if !header.interlaced {
+        return filter_rows(raw, stride, unit, filter);
+    }
+
+    let bits = header.bits_per_pixel();
+    let mut output = Vec::with_capacity(filtered_len(header));
+    for (x_start, y_start, x_step, y_step) in ADAM7_PASSES {
+        let pass_width = width.saturating_sub(x_start).div_ceil(x_step);
+        let pass_height = height.saturating_sub(y_start).div_ceil(y_step);
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+
+        let pass_stride = scanline_len(header, pass_width);
+        let mut pass = vec![0; pass_stride * pass_height];
+        for pass_y in 0..pass_height {
+            let y = y_start + pass_y * y_step;
+            let source = &raw[y * stride..(y + 1) * stride];
+            let target = &mut pass[pass_y * pass_stride..(pass_y + 1) * pass_stride];
+            for pass_x in 0..pass_width {
+                let x = x_start + pass_x * x_step;
+                if bits >= 8 {
+                    let bytes = bits / 8;
+                    target[pass_x * bytes..(pass_x + 1) * bytes]
+                        .copy_from_slice(&source[x * bytes..(x + 1) * bytes]);
+                } else {
+                    set_bits(target, pass_x, bits, get_bits(source, x, bits));
+                }
+            }
+        }
+        output.extend_from_slice(&filter_rows(&pass, pass_stride, unit, filter));
+    }
+
+    output
+}
+
+fn get_bits(row: &[u8], index: usize, bits: usize) -> u8 {
+    let bit_offset = index * bits;
+    let shift = 8 - bits - bit_offset % 8;
+    (row[bit_offset / 8] >> shift) & ((1 << bits) - 1) as u8
+}
+
+fn set_bits(row: &mut [u8], index: usize, bits: usize, value: u8) {
+    let bit_offset = index * bits;
+    let shift = 8 - bits - bit_offset % 8;
+    let mask = (((1 << bits) - 1) as u8) << shift;
+    row[bit_offset / 8] = (row[bit_offset / 8] & !mask) | ((value << shift) & mask);
+}
+
+/// Inflates and unfilters image data, returning non-interlaced scanlines without
+/// filter bytes. Interlaced images are deinterlaced. The dimensions are checked
+/// against `limits` before anything is inflated.
+pub fn decode_pixels(
+    header: &ImageHeader,
+    compressed: &[u8],
+    limits: &DecodeLimits,
+) -> Result<Vec<u8>, String> {
+    decode_pixel_parts(header, &[compressed], limits)
+}
+
+/// Like `decode_pixels`, for image data still split across IDAT chunks.
+pub fn decode_pixel_parts(
+    header: &ImageHeader,
+    parts: &[&[u8]],
+    limits: &DecodeLimits,
+) -> Result<Vec<u8>, String> {
+    limits.check_dimensions(header)?;
+    let expected = filtered_len(header);
+    if expected > limits.max_decompressed_size {
+        return Err(format!(
+            "image data inflates to {} bytes, more than the limit of {}",
+            expected, limits.max_decompressed_size
+        ));
+    }
+    let data = zlib::decompress_parts(parts, limits.max_decompressed_size)?;
+    unfilter_image(header, &data)
+}
+
+/// Number of inflated bytes, filter type bytes included, that the image data of
+/// `header` should have.
+pub fn filtered_len(header: &ImageHeader) -> usize {
+    let width = header.width as usize;
+    let height = header.height as usize;
+    if !header.interlaced {
+        return (scanline_len(header, width) + 1) * height;
+    }
+
+    ADAM7_PASSES
+        .iter()
+        .map(|&(x_start, y_start, x_step, y_step)| {
+            let pass_width = width.saturating_sub(x_start).div_ceil(x_step);
+            let pass_height = height.saturating_sub(y_start).div_ceil(y_step);
+            if pass_width == 0 {
+                0
+            } else {
+                (scanline_len(header, pass_width) + 1) * pass_height
+            }
+        })
+        .sum()
+}
+
+/// Unfilters (and deinterlaces) already inflated image data.
+pub fn unfilter_image(header: &ImageHeader, data: &[u8]) -> Result<Vec<u8>, String> {
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let stride = scanline_len(header, width);
+    let unit = filter_unit(header);
+
+    if !header.interlaced {
+        return Ok(unfilter(data, stride, height, unit)?.0);
+    }
+
+    // Check the data before allocating, the header alone can claim any size.
+    let expected = filtered_len(header);
+    if data.len() < expected {
+        return Err(format!(
+            "image data is truncated, expected {} bytes but found {}",
+            expected,
+            data.len()
+        ));
+    }
+
+    let bits = header.bits_per_pixel();
+    let size = stride
+        .checked_mul(height)
+        .ok_or("image dimensions overflow")?;
+    let mut output = vec![0; size];
+    let mut offset = 0;
+
+    for (x_start, y_start, x_step, y_step) in ADAM7_PASSES {
+        let pass_width = width.saturating_sub(x_start).div_ceil(x_step);
+        let pass_height = height.saturating_sub(y_start).div_ceil(y_step);
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+
+        let pass_stride = scanline_len(header, pass_width);
+        let (rows, used) = unfilter(&data[offset..], pass_stride, pass_height, unit)?;
+        offset += used;
+
+        for pass_y in 0..pass_height {
+            let source = &rows[pass_y * pass_stride..(pass_y + 1) * pass_stride];
+            let y = y_start + pass_y * y_step;
+            let target = &mut output[y * stride..(y + 1) * stride];
+            for pass_x in 0..pass_width {
+                let x = x_start + pass_x * x_step;
+                if bits >= 8 {
+                    let bytes = bits / 8;
+                    target[x * bytes..(x + 1) * bytes]
+                        .copy_from_slice(&source[pass_x * bytes..(pass_x + 1) * bytes]);
+                } else {
+                    set_bits(target, x, bits, get_bits(source, pass_x, bits));
+                }
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+/// Filters and deflates non-interlaced scanlines into the contents of an IDAT stream,
+/// interlacing them first if `header` says so.
+pub fn encode_pixels(header: &ImageHeader, raw: &[u8]) -> Result<Vec<u8>, String> {
+    encode_pixels_with_filter(header, raw, None)
+}
+
+/// Like `encode_pixels`, but `Some(filter)` uses filter type `filter` (0 to 4) on
+/// every scanline instead of choosing one per row.
+pub fn encode_pixels_with_filter(
+    header: &ImageHeader,
+    raw: &[u8],
+    filter: Option<u8>,
+) -> Result<Vec<u8>, String> {
+    if let Some(filter) = filter.filter(|&filter| filter > 4) {
+        return Err(format!("invalid filter type {}", filter));
+    }
+    let expected = scanline_len(header, header.width as usize) * header.height as usize;
+    if raw.len() != expected {
+        return Err(format!(
+            "expected {} bytes of pixel data, found {}",
+            expected,
+            raw.len()
+        ));
+    }
+    Ok(zlib::compress(&filter_image(header, raw, filter)))
+}
+
+/// Reads sample `index` of a scanline at the given bit depth.
+fn sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8 => row[index] as u16,
+        bits => get_bits(row, index, bits as usize) as u16,
+    }
+}
+
+/// Scales a sample of the given bit depth to 16 bits by bit replication, so the
+/// maximum value of every depth maps to 65535.
+fn scale_to_u16(value: u16, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => value,
+        bits => (value as u32 * 65535 / ((1 << bits) - 1)) as u16,
+    }
+}
+
+/// Converts unfiltered scanlines into 16-bit RGBA samples, using the PLTE and tRNS
+/// chunk data when present. Lower bit depths are scaled up exactly, so the result
+/// only depends on the pixels and not on how they were stored.
+pub fn rgba16_pixels(
+    header: &ImageHeader,
+    raw: &[u8],
+    palette: Option<&[u8]>,
+    transparency: Option<&[u8]>,
+) -> Result<Vec<[u16; 4]>, String> {
+    let width = header.width as usize;
+    let stride = scanline_len(header, width);
+    let palette = check_palette(header, palette)?;
+    if raw.len() < stride * header.height as usize {
+        return Err("image data is truncated".to_string());
+    }
+
+    let mut pixels = Vec::with_capacity(width * header.height as usize);
+    for (y, row) in raw.chunks(stride).take(header.height as usize).enumerate() {
+        rgba16_row(header, row, y, palette, transparency, &mut pixels)?;
+    }
+    Ok(pixels)
+}
+
+fn check_palette<'a>(header: &ImageHeader, palette: Option<&'a [u8]>) -> Result<&'a [u8], String> {
+    match (header.color_type, palette) {
+        (ColorType::Indexed, None) => Err("indexed image without PLTE chunk".into()),
+        (_, palette) => Ok(palette.unwrap_or(&[])),
+    }
+}
+
+/// Appends the 16-bit RGBA samples of scanline `y` to `pixels`.
+fn rgba16_row(
+    header: &ImageHeader,
+    row: &[u8],
+    y: usize,
+    palette: &[u8],
+    transparency: Option<&[u8]>,
+    pixels: &mut Vec<[u16; 4]>,
+) -> Result<(), String> {
+    let depth = header.bit_depth;
+    let transparent_key = |index: usize| {
+        transparency
+            .filter(|trns| trns.len() >= index * 2 + 2)
+            .map(|trns| u16::from_be_bytes([trns[index * 2], trns[index * 2 + 1]]))
+    };
+    let scale = |value: u16| scale_to_u16(value, depth);
+
+    for x in 0..header.width as usize {
+        let rgba = match header.color_type {
+            ColorType::Grayscale => {
+                let gray = sample(row, x, depth);
+                let alpha = if transparent_key(0) == Some(gray) {
+                    0
+                } else {
+                    65535
+                };
+                let gray = scale(gray);
+                [gray, gray, gray, alpha]
+            }
+            ColorType::GrayscaleAlpha => {
+                let gray = scale(sample(row, x * 2, depth));
+                [gray, gray, gray, scale(sample(row, x * 2 + 1, depth))]
+            }
+            ColorType::Rgb => {
+                let rgb = [
+                    sample(row, x * 3, depth),
+                    sample(row, x * 3 + 1, depth),
+                    sample(row, x * 3 + 2, depth),
+                ];
+                let key = [transparent_key(0), transparent_key(1), transparent_key(2)];
+                let alpha = if key == rgb.map(Some) { 0 } else { 65535 };
+                [scale(rgb[0]), scale(rgb[1]), scale(rgb[2]), alpha]
+            }
+            ColorType::Rgba => [
+                scale(sample(row, x * 4, depth)),
+                scale(sample(row, x * 4 + 1, depth)),
+                scale(sample(row, x * 4 + 2, depth)),
+                scale(sample(row, x * 4 + 3, depth)),
+            ],
+            ColorType::Indexed => {
+                let index = sample(row, x, depth) as usize;
+                let entry = palette.get(index * 3..index * 3 + 3).ok_or_else(|| {
+                    format!("palette index {} out of range at ({}, {})", index, x, y)
+                })?;
+                let alpha = transparency
+                    .and_then(|trns| trns.get(index))
+                    .copied()
+                    .unwrap_or(255);
+                [entry[0], entry[1], entry[2], alpha].map(|value| value as u16 * 257)
+            }
+        };
+        pixels.push(rgba);
+    }
+    Ok(())
+}
+
+/// Unfilters one pass of the image, or the whole image if it is not interlaced, one
+/// scanline at a time as it is inflated.
+struct RowReader<R> {
+    reader: R,
+    unit: usize,
+    line: Vec<u8>,
+    previous: Vec<u8>,
+    current: Vec<u8>,
+    y: usize,
+}
+
+impl<R: Read> RowReader<R> {
+    fn new(reader: R, stride: usize, unit: usize) -> Self {
+        RowReader {
+            reader,
+            unit,
+            line: vec![0; stride + 1],
+            previous: vec![0; stride],
+            current: vec![0; stride],
+            y: 0,
+        }
+    }
+
+    fn next_row(&mut self) -> Result<&[u8], String> {
+        self.reader
+            .read_exact(&mut self.line)
+            .map_err(|e| format!("invalid zlib stream: {}", e))?;
+        std::mem::swap(&mut self.previous, &mut self.current);
+        let previous = (self.y > 0).then_some(self.previous.as_slice());
+        unfilter_row(&self.line, previous, &mut self.current, self.unit, self.y)?;
+        self.y += 1;
+        Ok(&self.current)
+    }
+}
+
+/// Decodes the image data split across `parts` into 16-bit RGBA samples like
+/// `rgba16_pixels`, calling `row` with each scanline from top to bottom. Only a few
+/// rows are held in memory, so only the dimension limits apply and not the pixel or
+/// decompressed size limits. Interlaced images inflate the stream once per Adam7
+/// pass, to read the passes side by side.
+pub fn for_each_rgba16_row<F>(
+    header: &ImageHeader,
+    parts: &[&[u8]],
+    palette: Option<&[u8]>,
+    transparency: Option<&[u8]>,
+    limits: &DecodeLimits,
+    mut row: F,
+) -> Result<(), String>
+where
+    F: FnMut(&[[u16; 4]]),
+{
+    limits.check_width_and_height(header)?;
+    let palette = check_palette(header, palette)?;
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let stride = scanline_len(header, width);
+    let unit = filter_unit(header);
+    let mut pixels = Vec::with_capacity(width);
+
+    if !header.interlaced {
+        let mut rows = RowReader::new(zlib::decoder(parts), stride, unit);
+        for y in 0..height {
+            pixels.clear();
+            rgba16_row(
+                header,
+                rows.next_row()?,
+                y,
+                palette,
+                transparency,
+                &mut pixels,
+            )?;
+            row(&pixels);
+        }
+        return Ok(());
+    }
+
+    // One reader per pass, each skipping the inflated bytes of the passes before it.
+    let mut passes = Vec::new();
+    let mut offset = 0;
+    for (x_start, y_start, x_step, y_step) in ADAM7_PASSES {
+        let pass_width = width.saturating_sub(x_start).div_ceil(x_step);
+        let pass_height = height.saturating_sub(y_start).div_ceil(y_step);
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+        let pass_stride = scanline_len(header, pass_width);
+
+        let mut reader = zlib::decoder(parts);
+        let skipped = io::copy(&mut (&mut reader).take(offset as u64), &mut io::sink())
+            .map_err(|e| format!("invalid zlib stream: {}", e))?;
+        if skipped != offset as u64 {
+            return Err("invalid zlib stream: image data is truncated".to_string());
+        }
+        offset += (pass_stride + 1) * pass_height;
+        passes.push((
+            (x_start, y_start, x_step, y_step, pass_width),
+            RowReader::new(reader, pass_stride, unit),
+        ));
+    }
+
+    let bits = header.bits_per_pixel();
+    let mut target = vec![0; stride];
+    for y in 0..height {
+        for ((x_start, y_start, x_step, y_step, pass_width), rows) in passes.iter_mut() {
+            if y < *y_start || (y - *y_start) % *y_step != 0 {
+                continue;
+            }
+            let source = rows.next_row()?;
+            for pass_x in 0..*pass_width {
+                let x = *x_start + pass_x * *x_step;
+                if bits >= 8 {
+                    let bytes = bits / 8;
+                    target[x * bytes..(x + 1) * bytes]
+                        .copy_from_slice(&source[pass_x * bytes..(pass_x + 1) * bytes]);
+                } else {
+                    set_bits(&mut target, x, bits, get_bits(source, pass_x, bits));
+                }
+            }
+        }
+        pixels.clear();
+        rgba16_row(header, &target, y, palette, transparency, &mut pixels)?;
+        row(&pixels);
+    }
+    Ok(())
+}
+
+/// An 8-bit RGBA image with non-premultiplied alpha.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// Creates a fully transparent black image.
+    pub fn new(width: u32, height: u32) -> Self {
+        RgbaImage {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    /// Converts unfiltered scanlines into RGBA, using the PLTE and tRNS chunk data
+    /// when present. 16-bit samples keep their high byte.
+    pub fn from_raw(
+        header: &ImageHeader,
+        raw: &[u8],
+        palette: Option<&[u8]>,
+        transparency: Option<&[u8]>,
+    ) -> Result<Self, String> {
+        let pixels = rgba16_pixels(header, raw, palette, transparency)?;
+        Ok(RgbaImage {
+            width: header.width,
+            height: header.height,
+            pixels: pixels
+                .iter()
+                .flat_map(|rgba| rgba.map(|value| (value >> 8) as u8))
+                .collect(),
+        })
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let offset = (y as usize * self.width as usize + x as usize) * 4;
+        [
+            self.pixels[offset],
+            self.pixels[offset + 1],
+            self.pixels[offset + 2],
+            self.pixels[offset + 3],
+        ]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, rgba: [u8; 4]) {
+        let offset = (y as usize * self.width as usize + x as usize) * 4;
+        self.pixels[offset..offset + 4].copy_from_slice(&rgba);
+    }
+
+    /// Copies the `width` x `height` rectangle starting at (`x`, `y`) into a new image.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> RgbaImage {
+        let mut cropped = RgbaImage::new(width, height);
+        for row in 0..height {
+            let start = ((y + row) as usize * self.width as usize + x as usize) * 4;
+            let end = start + width as usize * 4;
+            let target = row as usize * width as usize * 4;
+            cropped.pixels[target..target + width as usize * 4]
+                .copy_from_slice(&self.pixels[start..end]);
+        }
+        cropped
+    }
+
+    /// The IHDR describing this image as 8-bit RGBA.
+    pub fn header(&self) -> ImageHeader {
+        ImageHeader {
+            width: self.width,
+            height: self.height,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        }
+    }
+
+    /// Encodes this image as a standalone 8-bit RGBA `Png`.
+    pub fn to_png(&self) -> Png {
+        let header = self.header();
+        let data = encode_pixels(&header, &self.pixels).expect("RGBA buffer matches its header");
+        Png::from_chunks(vec![
+            header.to_chunk(),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), data),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ])
+    }
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(width: u32, height: u32, bit_depth: u8, color_type: ColorType) -> ImageHeader {
+        ImageHeader {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        }
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let header = header(3, 3, 8, ColorType::Rgb);
+        let raw: Vec<u8> = (0..27).map(|i| (i * 37 % 251) as u8).collect();
+
+        let filtered = filter_image(&header, &raw, None);
+        let (unfiltered, used) = unfilter(&filtered, 9, 3, 3).unwrap();
+
+        assert_eq!(used, filtered.len());
+        assert_eq!(unfiltered, raw);
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let header = header(5, 2, 2, ColorType::Grayscale);
+        let raw = vec![0b00011011, 0b11000000, 0b10010011, 0b01000000];
+
+        let compressed = encode_pixels(&header, &raw).unwrap();
+
+        assert_eq!(
+            decode_pixels(&header, &compressed, &DecodeLimits::default()).unwrap(),
+            raw
+        );
+    }
+
+    #[test]
+    fn test_decode_pixels_checks_limits_before_inflating() {
+        let compressed = encode_pixels(&header(5, 2, 8, ColorType::Rgb), &[0; 30]).unwrap();
+
+        // The header claims far more pixels than the data holds.
+        let huge = header(1_000_000, 1_000_000, 8, ColorType::Rgb);
+        assert!(decode_pixels(&huge, &compressed, &DecodeLimits::default())
+            .unwrap_err()
+            .contains("limit"));
+
+        let limits = DecodeLimits {
+            max_decompressed_size: 10,
+            ..DecodeLimits::default()
+        };
+        assert!(decode_pixels(&header(5, 2, 8, ColorType::Rgb), &compressed, &limits).is_err());
+    }
+
+    #[test]
+    fn test_filtered_len() {
+        let mut header = header(8, 8, 8, ColorType::Grayscale);
+        assert_eq!(filtered_len(&header), 9 * 8);
+
+        // Passes of 1x1, 1x1, 2x1, 2x2, 4x2, 4x4 and 8x4 pixels, plus a filter byte per row.
+        header.interlaced = true;
+        assert_eq!(filtered_len(&header), 2 + 2 + 3 + 6 + 10 + 20 + 36);
+    }
+
+    #[test]
+    fn test_unfilter_rejects_bad_filter_type() {
+        let header = header(1, 1, 8, ColorType::Grayscale);
+        assert!(unfilter_image(&header, &[5, 0]).is_err());
+        assert!(unfilter_image(&header, &[0]).is_err());
+    }
+
+    #[test]
+    fn test_deinterlace() {
+        let mut header = header(2, 2, 8, ColorType::Grayscale);
+        header.interlaced = true;
+        // Pass 1 holds (0, 0), pass 6 holds (1, 0) and pass 7 holds the second row.
+        let data = [0, 10, 0, 20, 0, 30, 40];
+
+        assert_eq!(unfilter_image(&header, &data).unwrap(), [10, 20, 30, 40]);
+    }
+
+    #[test]
+    fn test_unfilter_checks_data_before_allocating() {
+        // Deinterlacing this would need an 8 TiB buffer.
+        let mut header = header(1 << 20, 1 << 20, 16, ColorType::Rgba);
+        header.interlaced = true;
+
+        assert!(unfilter_image(&header, &[0; 64])
+            .unwrap_err()
+            .contains("truncated"));
+    }
+
+    #[test]
+    fn test_encode_interlaced_with_every_filter() {
+        // 16 pixels wide so 1-bit rows have no padding bits, which decode as zeros.
+        for (bit_depth, color_type) in [(1, ColorType::Grayscale), (16, ColorType::Rgba)] {
+            let mut header = header(16, 9, bit_depth, color_type);
+            header.interlaced = true;
+            let len = scanline_len(&header, 16) * 9;
+            let raw: Vec<u8> = (0..len).map(|i| (i * 73 % 251) as u8).collect();
+
+            for filter in 0..5 {
+                let compressed = encode_pixels_with_filter(&header, &raw, Some(filter)).unwrap();
+                let filtered = zlib::decompress(&compressed, usize::MAX).unwrap();
+
+                assert_eq!(filtered.len(), filtered_len(&header));
+                assert_eq!(filtered[0], filter);
+                assert_eq!(unfilter_image(&header, &filtered).unwrap(), raw);
+            }
+        }
+
+        let header = header(1, 1, 8, ColorType::Grayscale);
+        assert!(encode_pixels_with_filter(&header, &[0], Some(5)).is_err());
+    }
+
+    #[test]
+    fn test_rgba_from_low_bit_depth_gray() {
+        let header = header(4, 1, 2, ColorType::Grayscale);
+        let transparency = 2u16.to_be_bytes();
+
+        let image = RgbaImage::from_raw(&header, &[0b00011011], None, Some(&transparency)).unwrap();
+
+        assert_eq!(image.pixel(0, 0), [0, 0, 0, 255]);
+        assert_eq!(image.pixel(1, 0), [85, 85, 85, 255]);
+        assert_eq!(image.pixel(2, 0), [170, 170, 170, 0]);
+        assert_eq!(image.pixel(3, 0), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_rgba_from_palette() {
+        let header = header(2, 1, 8, ColorType::Indexed);
+        let palette = [255, 0, 0, 0, 0, 255];
+
+        let image = RgbaImage::from_raw(&header, &[1, 0], Some(&palette), Some(&[128])).unwrap();
+        assert_eq!(image.pixel(0, 0), [0, 0, 255, 255]);
+        assert_eq!(image.pixel(1, 0), [255, 0, 0, 128]);
+
+        assert!(RgbaImage::from_raw(&header, &[2, 0], Some(&palette), None).is_err());
+        assert!(RgbaImage::from_raw(&header, &[0, 0], None, None).is_err());
+    }
+
+    #[test]
+    fn test_rgba_crop() {
+        let mut image = RgbaImage::new(3, 3);
+        image.set_pixel(1, 1, [1, 2, 3, 4]);
+        image.set_pixel(2, 2, [5, 6, 7, 8]);
+
+        let cropped = image.crop(1, 1, 2, 2);
+
+        assert_eq!((cropped.width, cropped.height), (2, 2));
+        assert_eq!(cropped.pixel(0, 0), [1, 2, 3, 4]);
+        assert_eq!(cropped.pixel(1, 1), [5, 6, 7, 8]);
+        assert_eq!(cropped.pixel(1, 0), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_rgba_to_png() {
+        let mut image = RgbaImage::new(3, 2);
+        image.set_pixel(2, 1, [1, 2, 3, 4]);
+
+        let png = image.to_png();
+
+        assert_eq!(png.decode_rgba().unwrap(), image);
+    }
+}
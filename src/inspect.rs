@@ -0,0 +1,607 @@
+use crate::chunk::Chunk;
+use crate::ihdr::{ColorType, ImageHeader};
+use crate::image;
+use crate::limits::DecodeLimits;
+use crate::payload;
+use crate::png::Png;
+use crate::recover;
+use crate::registry;
+use crate::{crypto, zlib};
+
+use std::fmt;
+
+/// Ancillary chunks above these sizes are reported. Registered chunks such as iCCP and
+/// eXIf can legitimately be large, so they get more room.
+const LARGE_CHUNK: usize = 64 * 1024;
+const LARGE_KNOWN_CHUNK: usize = 1024 * 1024;
+
+/// Text shorter than this is too short for a meaningful entropy estimate.
+const MIN_ENTROPY_LEN: usize = 64;
+/// English prose sits around 4.2 bits per byte, base64 near 6 and random bytes near 8.
+const HIGH_ENTROPY: f64 = 5.0;
+
+/// A chi-square p-value above this means the LSB value pairs are suspiciously even.
+const CHI_SQUARE_THRESHOLD: f64 = 0.95;
+/// Value pairs with fewer samples than this are left out of the chi-square test.
+const MIN_PAIR_COUNT: u32 = 10;
+
+/// One suspicious property of a file, with how strongly it suggests hidden data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub score: u32,
+    pub description: String,
+}
+
+/// The findings of `inspect`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    fn add(&mut self, score: u32, description: String) {
+        self.findings.push(Finding { score, description });
+    }
+
+    /// Sum of the finding scores, capped at 100.
+    pub fn score(&self) -> u32 {
+        self.findings
+            .iter()
+            .map(|finding| finding.score)
+            .sum::<u32>()
+            .min(100)
+    }
+
+    pub fn verdict(&self) -> &'static str {
+        match self.score() {
+            0 => "clean",
+            1..=24 => "low",
+            25..=59 => "suspicious",
+            _ => "likely hiding data",
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "score {}/100 ({})", self.score(), self.verdict())?;
+        for finding in self.findings.iter() {
+            writeln!(f, "  [{:>2}] {}", finding.score, finding.description)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks a PNG file for places data is commonly hidden in.
+pub fn inspect(bytes: &[u8]) -> Report {
+    inspect_with_limits(bytes, DecodeLimits::default())
+}
+
+/// Like `inspect`, with `limits` instead of the defaults.
+pub fn inspect_with_limits(bytes: &[u8], limits: DecodeLimits) -> Report {
+    let mut report = Report::default();
+
+    let png = match Png::from_bytes_with_limits(bytes, limits) {
+        Ok(png) => png,
+        Err(e) => {
+            report.add(10, format!("file could not be parsed: {}", e));
+            // Walk whatever chunks are still there, a damaged file can carry a
+            // trailer or suspicious chunks just as well.
+            recover::parse_lenient(bytes, limits).png
+        }
+    };
+
+    if png.chunk_by_type("IEND").is_none() {
+        report.add(10, "no IEND chunk found".to_string());
+    }
+    if !png.trailer().is_empty() {
+        report.add(
+            40,
+            format!("{} bytes of data after IEND", png.trailer().len()),
+        );
+    }
+
+    for chunk in png.chunks() {
+        check_chunk(&mut report, chunk, &limits);
+    }
+    if let Ok(header) = png.image_header() {
+        check_image_data(&mut report, &png, &header);
+    }
+
+    report
+}
+
+fn check_chunk(report: &mut Report, chunk: &Chunk, limits: &DecodeLimits) {
+    let chunk_type = chunk.chunk_type();
+    let data = chunk.data();
+    let known = registry::lookup(chunk_type).is_some();
+
+    if !known && !chunk_type.is_public() {
+        report.add(15, format!("unknown private chunk {}", chunk_type));
+    }
+
+    if !chunk_type.is_critical() {
+        let limit = if known {
+            LARGE_KNOWN_CHUNK
+        } else {
+            LARGE_CHUNK
+        };
+        if data.len() > limit {
+            report.add(
+                15,
+                format!(
+                    "{} chunk is unusually large ({} bytes)",
+                    chunk_type,
+                    data.len()
+                ),
+            );
+        }
+    }
+
+    if crypto::is_sealed(data) || payload::is_part(data) || payload::is_compressed(data) {
+        report.add(
+            30,
+            format!("{} chunk holds a message written by this tool", chunk_type),
+        );
+    }
+
+    let name = chunk_type.to_string();
+    if let Some(stream) = compressed_stream(&name, data) {
+        match zlib::decompress_prefix(stream, limits.max_decompressed_size) {
+            Ok((_, used)) if used < stream.len() => report.add(
+                25,
+                format!(
+                    "{} chunk has {} bytes after its zlib stream",
+                    chunk_type,
+                    stream.len() - used
+                ),
+            ),
+            Ok(_) => {}
+            Err(e) => report.add(5, format!("{} chunk: {}", chunk_type, e)),
+        }
+    }
+
+    if let Some(text) = text_value(&name, data, limits) {
+        let entropy = entropy(&text);
+        if text.len() >= MIN_ENTROPY_LEN && entropy > HIGH_ENTROPY {
+            report.add(
+                20,
+                format!(
+                    "{} chunk text looks encoded or encrypted ({:.2} bits per byte)",
+                    chunk_type, entropy
+                ),
+            );
+        }
+    }
+}
+
+/// The zlib stream inside zTXt, iCCP and compressed iTXt chunks.
+fn compressed_stream<'a>(name: &str, data: &'a [u8]) -> Option<&'a [u8]> {
+    let keyword_end = data.iter().position(|&byte| byte == 0)?;
+    let rest = &data[keyword_end + 1..];
+    match name {
+        "zTXt" | "iCCP" => rest.get(1..),
+        // Compression flag, method, language tag and translated keyword.
+        "iTXt" if rest.first() == Some(&1) => {
+            let language_end = rest.get(2..)?.iter().position(|&byte| byte == 0)? + 3;
+            let translated_end = rest
+                .get(language_end..)?
+                .iter()
+                .position(|&byte| byte == 0)?
+                + language_end
+                + 1;
+            rest.get(translated_end..)
+        }
+        _ => None,
+    }
+}
+
+/// The text of tEXt, zTXt and iTXt chunks, decompressed where needed.
+fn text_value(name: &str, data: &[u8], limits: &DecodeLimits) -> Option<Vec<u8>> {
+    let keyword_end = data.iter().position(|&byte| byte == 0)?;
+    match name {
+        "tEXt" => Some(data[keyword_end + 1..].to_vec()),
+        "zTXt" | "iTXt" => match compressed_stream(name, data) {
+            Some(stream) => zlib::decompress_prefix(stream, limits.max_decompressed_size)
+                .ok()
+                .map(|(text, _)| text),
+            None if name == "iTXt" => {
+                let rest = data.get(keyword_end + 3..)?;
+                let language_end = rest.iter().position(|&byte| byte == 0)? + 1;
+                let translated_end =
+                    rest[language_end..].iter().position(|&byte| byte == 0)? + language_end + 1;
+                Some(rest[translated_end..].to_vec())
+            }
+            None => None,
+        },
+        _ => None,
+    }
+}
+
+/// Shannon entropy of `data` in bits per byte.
+fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / data.len() as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn check_image_data(report: &mut Report, png: &Png, header: &ImageHeader) {
+    // The header alone can claim terabytes, check it before inflating or unfiltering.
+    let limits = png.limits();
+    if let Err(e) = limits.check_dimensions(header) {
+        report.add(5, format!("image data not checked: {}", e));
+        return;
+    }
+    let expected = image::filtered_len(header);
+    if expected > limits.max_decompressed_size {
+        report.add(
+            5,
+            format!(
+                "image data not checked: it inflates to {} bytes, more than the limit of {}",
+                expected, limits.max_decompressed_size
+            ),
+        );
+        return;
+    }
+
+    let compressed = png.idat_data();
+    let inflated = match zlib::decompress_prefix(&compressed, limits.max_decompressed_size) {
+        Ok((inflated, used)) => {
+            if used < compressed.len() {
+                report.add(
+                    30,
+                    format!(
+                        "{} bytes after the IDAT zlib stream",
+                        compressed.len() - used
+                    ),
+                );
+            }
+            inflated
+        }
+        Err(_) => return,
+    };
+
+    if inflated.len() > expected {
+        report.add(
+            30,
+            format!(
+                "{} bytes of image data after the last scanline",
+                inflated.len() - expected
+            ),
+        );
+    }
+
+    if header.bit_depth < 8 || header.color_type == ColorType::Indexed {
+        return;
+    }
+    let raw = match image::unfilter_image(header, &inflated) {
+        Ok(raw) => raw,
+        Err(_) => return,
+    };
+
+    let channels = header.color_type.channels();
+    let bytes_per_sample = header.bit_depth as usize / 8;
+    let names = match header.color_type {
+        ColorType::Grayscale | ColorType::GrayscaleAlpha => ["gray", "alpha", "", ""],
+        _ => ["red", "green", "blue", "alpha"],
+    };
+    for (channel, name) in names.iter().enumerate().take(channels) {
+        // The low byte holds the least significant bits of a 16-bit sample.
+        let samples = raw
+            .chunks(bytes_per_sample)
+            .skip(channel)
+            .step_by(channels)
+            .map(|sample| sample[bytes_per_sample - 1]);
+        if let Some(p) = chi_square_lsb(samples) {
+            if p > CHI_SQUARE_THRESHOLD {
+                report.add(
+                    30,
+                    format!(
+                        "least significant bits of the {} channel look randomized (chi-square p = {:.3})",
+                        name, p
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Westfeld and Pfitzmann's chi-square attack. Embedding random bits in the LSBs
+/// evens out the counts of each pair of values 2k and 2k+1, which drives the p-value
+/// towards 1. Returns `None` when there are too few samples to tell.
+fn chi_square_lsb(samples: impl Iterator<Item = u8>) -> Option<f64> {
+    let mut histogram = [0u32; 256];
+    for sample in samples {
+        histogram[sample as usize] += 1;
+    }
+
+    let mut statistic = 0.0;
+    let mut pairs = 0;
+    for pair in histogram.chunks(2) {
+        let total = pair[0] + pair[1];
+        if total < MIN_PAIR_COUNT {
+            continue;
+        }
+        let expected = total as f64 / 2.0;
+        statistic += (pair[0] as f64 - expected).powi(2) / expected;
+        pairs += 1;
+    }
+
+    if pairs < 2 {
+        return None;
+    }
+    Some(1.0 - gamma_p((pairs - 1) as f64 / 2.0, statistic / 2.0))
+}
+
+/// The regularized lower incomplete gamma function P(a, x), i.e. the chi-square CDF
+/// with 2a degrees of freedom at 2x.
+fn gamma_p(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let prefix = (a * x.ln() - x - ln_gamma(a)).exp();
+
+    if x < a + 1.0 {
+        // Series expansion.
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        let mut n = a;
+        for _ in 0..500 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        sum * prefix
+    } else {
+        // Continued fraction for Q(a, x), evaluated with Lentz's method.
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..500 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        1.0 - prefix * h
+    }
+}
+
+/// ln Γ(x) for x > 0, using the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.001_208_650_973_866_179,
+        -0.000_005_395_239_384_953,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let mut series = 1.000_000_000_190_015;
+    for (i, coefficient) in COEFFICIENTS.iter().enumerate() {
+        series += coefficient / (x + 1.0 + i as f64);
+    }
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn gray_png(pixels: &[u8], width: u32) -> Png {
+        let header = ImageHeader {
+            width,
+            height: pixels.len() as u32 / width,
+            bit_depth: 8,
+            color_type: ColorType::Grayscale,
+            interlaced: false,
+        };
+        Png::from_chunks(vec![
+            header.to_chunk(),
+            chunk("IDAT", &image::encode_pixels(&header, pixels).unwrap()),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    /// A smooth gradient using only even values, as a stand-in for a clean image.
+    fn gradient() -> Vec<u8> {
+        (0..64 * 64)
+            .map(|i| ((i % 64 + i / 64) * 2) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_image() {
+        let report = inspect(&gray_png(&gradient(), 64).as_bytes());
+
+        assert_eq!(report.findings, []);
+        assert_eq!(report.verdict(), "clean");
+    }
+
+    #[test]
+    fn test_data_after_iend() {
+        let bytes = [gray_png(&gradient(), 64).as_bytes(), b"secret".to_vec()].concat();
+
+        let report = inspect(&bytes);
+
+        assert!(report.findings[0]
+            .description
+            .contains("6 bytes of data after IEND"));
+        assert_eq!(report.score(), 40);
+    }
+
+    #[test]
+    fn test_huge_header_is_not_decoded() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/conformance/x-huge-interlaced.png");
+
+        let report = inspect(&std::fs::read(path).unwrap());
+
+        assert!(report
+            .findings
+            .iter()
+            .any(|finding| finding.description.starts_with("image data not checked")));
+    }
+
+    #[test]
+    fn test_limits_are_passed_through() {
+        let bytes = gray_png(&gradient(), 64).as_bytes();
+        let limits = DecodeLimits {
+            max_pixels: 64 * 64 - 1,
+            ..DecodeLimits::default()
+        };
+
+        let report = inspect_with_limits(&bytes, limits);
+        assert!(report.findings[0]
+            .description
+            .contains("more than the limit of 4095"));
+    }
+
+    #[test]
+    fn test_damaged_files_are_still_inspected() {
+        let mut bytes = [gray_png(&gradient(), 64).as_bytes(), b"secret".to_vec()].concat();
+        // Flip a bit in the IDAT data so its CRC no longer matches.
+        bytes[8 + 25 + 8] ^= 1;
+
+        let report = inspect(&bytes);
+        let descriptions: Vec<&str> = report
+            .findings
+            .iter()
+            .map(|finding| finding.description.as_str())
+            .collect();
+        assert!(descriptions[0].contains("could not be parsed"));
+        assert!(descriptions.contains(&"6 bytes of data after IEND"));
+
+        let truncated = gray_png(&gradient(), 64).as_bytes();
+        let report = inspect(&truncated[..truncated.len() - 12]);
+        assert!(report
+            .findings
+            .iter()
+            .any(|finding| finding.description == "no IEND chunk found"));
+    }
+
+    #[test]
+    fn test_suspicious_chunks() {
+        let mut png = gray_png(&gradient(), 64);
+        png.insert_chunk_before_iend(chunk("prIv", b"hidden"));
+        let encoded: String = (0..200u32)
+            .map(|i| {
+                (b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/"
+                    [(i * 37 % 64) as usize]) as char
+            })
+            .collect();
+        png.insert_chunk_before_iend(chunk("tEXt", &[b"Comment\0", encoded.as_bytes()].concat()));
+        png.insert_chunk_before_iend(chunk(
+            "tEXt",
+            b"Comment\0A perfectly ordinary comment about the picture, nothing to see here.",
+        ));
+        let padded = [zlib::compress(b"text"), b"extra".to_vec()].concat();
+        png.insert_chunk_before_iend(chunk("zTXt", &[b"Comment\0\0", padded.as_slice()].concat()));
+
+        let report = inspect(&png.as_bytes());
+        let descriptions: Vec<&str> = report
+            .findings
+            .iter()
+            .map(|finding| finding.description.as_str())
+            .collect();
+
+        assert_eq!(descriptions.len(), 3, "{:?}", descriptions);
+        assert!(descriptions[0].contains("unknown private chunk prIv"));
+        assert!(descriptions[1].contains("tEXt chunk text looks encoded"));
+        assert!(descriptions[2].contains("zTXt chunk has 5 bytes after its zlib stream"));
+    }
+
+    #[test]
+    fn test_trailing_image_data() {
+        let mut png = gray_png(&gradient(), 64);
+        let header = png.image_header().unwrap();
+        let raw = image::decode_pixels(&header, &png.idat_data(), png.limits()).unwrap();
+        let mut filtered = Vec::new();
+        for row in raw.chunks(64) {
+            filtered.push(0);
+            filtered.extend_from_slice(row);
+        }
+        filtered.extend_from_slice(b"extra");
+        let stream = [zlib::compress(&filtered), b"more".to_vec()].concat();
+        png.set_image_data(header, stream).unwrap();
+
+        let report = inspect(&png.as_bytes());
+
+        assert_eq!(report.findings.len(), 2, "{}", report);
+        assert!(report.findings[0]
+            .description
+            .contains("4 bytes after the IDAT"));
+        assert!(report.findings[1]
+            .description
+            .contains("5 bytes of image data"));
+    }
+
+    #[test]
+    fn test_chi_square_detects_randomized_lsbs() {
+        let mut state = 12345u32;
+        let randomized: Vec<u8> = gradient()
+            .iter()
+            .map(|value| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                value | ((state >> 16) & 1) as u8
+            })
+            .collect();
+
+        let report = inspect(&gray_png(&randomized, 64).as_bytes());
+
+        assert_eq!(report.findings.len(), 1, "{}", report);
+        assert!(report.findings[0]
+            .description
+            .contains("gray channel look randomized"));
+    }
+
+    #[test]
+    fn test_gamma_p() {
+        // Chi-square with 2 degrees of freedom has CDF 1 - e^(-x/2).
+        for x in [0.5, 2.0, 10.0] {
+            assert!((gamma_p(1.0, x / 2.0) - (1.0 - (-x / 2.0f64).exp())).abs() < 1e-9);
+        }
+        assert!((ln_gamma(5.0) - 24.0f64.ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(entropy(b"aaaa"), 0.0);
+        assert!((entropy(b"abcd") - 2.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,109 @@
+//! Reading, editing and writing PNG files at the chunk level, plus the image decoding,
+//! steganography, encryption and signing used by the `png-rs` command line tool.
+//!
+//! The CLI is behind the default `cli` feature. Depend on this crate with
+//! `default-features = false` to use the library without clap.
+//!
+//! # Adding and reading a chunk
+//!
+//! ```
+//! use png_rs::{Chunk, ChunkType, Png, RgbaImage};
+//! use std::str::FromStr;
+//!
+//! let bytes = RgbaImage::new(2, 2).to_png().as_bytes();
+//!
+//! let mut png = Png::try_from(bytes.as_slice())?;
+//! let chunk_type = ChunkType::from_str("ruSt")?;
+//! png.insert_chunk_before_iend(Chunk::new(chunk_type, b"hello".to_vec()));
+//!
+//! let png = Png::try_from(png.as_bytes().as_slice())?;
+//! assert_eq!(png.chunk_by_type("ruSt").unwrap().data_as_string()?, "hello");
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! # Stripping metadata
+//!
+//! Only ancillary chunks are ever removed, so the image itself stays intact.
+//!
+//! ```
+//! use png_rs::{Chunk, ChunkType, Png, RgbaImage};
+//! use std::str::FromStr;
+//!
+//! let mut png = RgbaImage::new(2, 2).to_png();
+//! let text = Chunk::new(ChunkType::from_str("tEXt")?, b"Author\0Me".to_vec());
+//! png.insert_chunk_before_iend(text);
+//!
+//! let removed = png.strip_chunks(|chunk_type| chunk_type.to_string() == "tEXt");
+//! assert_eq!(removed.len(), 1);
+//! assert!(png.chunk_by_type("tEXt").is_none());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! # Decoding untrusted files
+//!
+//! Parsing and decoding check [`DecodeLimits`], so a small file cannot make the
+//! decoder allocate gigabytes.
+//!
+//! ```
+//! use png_rs::{DecodeLimits, Png, RgbaImage};
+//!
+//! let mut image = RgbaImage::new(3, 2);
+//! image.set_pixel(1, 1, [255, 0, 0, 255]);
+//! let bytes = image.to_png().as_bytes();
+//!
+//! let limits = DecodeLimits {
+//!     max_pixels: 1 << 20,
+//!     ..DecodeLimits::default()
+//! };
+//! let png = Png::from_bytes_with_limits(&bytes, limits)?;
+//! assert_eq!(png.decode_rgba()?, image);
+//!
+//! let tiny = DecodeLimits {
+//!     max_pixels: 4,
+//!     ..DecodeLimits::default()
+//! };
+//! assert!(Png::from_bytes_with_limits(&bytes, tiny)?.decode_rgba().is_err());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! # Signing an image
+//!
+//! ```
+//! use ed25519_dalek::SigningKey;
+//! use png_rs::signature::Signature;
+//! use png_rs::RgbaImage;
+//!
+//! let key = SigningKey::from_bytes(&[7; 32]);
+//! let mut png = RgbaImage::new(2, 2).to_png();
+//!
+//! let signature = Signature::sign(png.chunks(), &[], &key)?;
+//! png.insert_chunk_before_iend(signature.to_chunk());
+//!
+//! assert!(signature.verify(png.chunks())?.is_valid());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod apng;
+pub mod chunk;
+pub mod chunk_type;
+pub mod crypto;
+pub mod edit;
+pub mod ihdr;
+pub mod image;
+pub mod inspect;
+pub mod limits;
+pub mod mapped;
+pub mod payload;
+pub mod png;
+pub mod recover;
+pub mod registry;
+pub mod signature;
+pub mod stego;
+pub mod zlib;
+
+pub use chunk::{Chunk, ChunkRef};
+pub use chunk_type::ChunkType;
+pub use ihdr::{ColorType, ImageHeader};
+pub use image::RgbaImage;
+pub use limits::DecodeLimits;
+pub use png::{Png, PngRef};
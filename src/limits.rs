@@ -0,0 +1,153 @@
+use crate::ihdr::ImageHeader;
+
+/// The PNG spec caps chunk lengths at 2^31 - 1, whatever the limits say.
+pub const MAX_CHUNK_LEN: u32 = i32::MAX as u32;
+
+/// Bounds on what decoding an untrusted file may allocate. Every parser and
+/// decompressor checks the relevant limit before allocating, so a small file cannot
+/// claim gigabytes of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Largest data length accepted for a single chunk.
+    pub max_chunk_size: u32,
+    /// Largest file accepted, trailer included, whether it is read into memory or
+    /// mapped with `Png::open_mmap`.
+    pub max_total_size: usize,
+    /// Most chunks accepted in one file.
+    pub max_chunks: usize,
+    /// Most bytes a single zlib stream may inflate to.
+    pub max_decompressed_size: usize,
+    pub max_width: u32,
+    pub max_height: u32,
+    /// Most pixels in an image, which bounds the decoded RGBA buffer.
+    pub max_pixels: u64,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_chunk_size: 64 << 20,
+            max_total_size: 1 << 30,
+            max_chunks: 1 << 18,
+            max_decompressed_size: 1 << 30,
+            max_width: 1_000_000,
+            max_height: 1_000_000,
+            max_pixels: 1 << 28,
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// No limits beyond what the PNG format itself allows.
+    pub fn unlimited() -> Self {
+        DecodeLimits {
+            max_chunk_size: MAX_CHUNK_LEN,
+            max_total_size: usize::MAX,
+            max_chunks: usize::MAX,
+            max_decompressed_size: usize::MAX,
+            max_width: u32::MAX,
+            max_height: u32::MAX,
+            max_pixels: u64::MAX,
+        }
+    }
+
+    pub fn check_chunk_length(&self, length: u32) -> Result<(), String> {
+        if length > MAX_CHUNK_LEN {
+            return Err(format!("chunk length {} exceeds 2^31 - 1", length));
+        }
+        if length > self.max_chunk_size {
+            return Err(format!(
+                "chunk length {} exceeds the limit of {} bytes",
+                length, self.max_chunk_size
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn check_total_size(&self, size: usize) -> Result<(), String> {
+        if size > self.max_total_size {
+            return Err(format!(
+                "file size {} exceeds the limit of {} bytes",
+                size, self.max_total_size
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn check_chunk_count(&self, count: usize) -> Result<(), String> {
+        if count > self.max_chunks {
+            return Err(format!("more than {} chunks", self.max_chunks));
+        }
+        Ok(())
+    }
+
+    /// Checks the width and height of `header` but not its pixel count, for decoders
+    /// that only hold a few rows in memory.
+    pub fn check_width_and_height(&self, header: &ImageHeader) -> Result<(), String> {
+        if header.width > self.max_width || header.height > self.max_height {
+            return Err(format!(
+                "image dimensions {}x{} exceed the limit of {}x{}",
+                header.width, header.height, self.max_width, self.max_height
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the dimensions of `header`, and that its decoded RGBA pixels fit in
+    /// memory.
+    pub fn check_dimensions(&self, header: &ImageHeader) -> Result<(), String> {
+        self.check_width_and_height(header)?;
+        let pixels = header.width as u64 * header.height as u64;
+        if pixels > self.max_pixels {
+            return Err(format!(
+                "image has {} pixels, more than the limit of {}",
+                pixels, self.max_pixels
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::ColorType;
+
+    fn header(width: u32, height: u32) -> ImageHeader {
+        ImageHeader {
+            width,
+            height,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        }
+    }
+
+    #[test]
+    fn test_chunk_length_is_capped_by_the_spec() {
+        let limits = DecodeLimits::unlimited();
+
+        assert!(limits.check_chunk_length(MAX_CHUNK_LEN).is_ok());
+        assert!(limits.check_chunk_length(MAX_CHUNK_LEN + 1).is_err());
+        assert!(DecodeLimits::default()
+            .check_chunk_length(MAX_CHUNK_LEN)
+            .is_err());
+    }
+
+    #[test]
+    fn test_check_dimensions() {
+        let limits = DecodeLimits {
+            max_width: 100,
+            max_height: 50,
+            max_pixels: 1000,
+            ..DecodeLimits::default()
+        };
+
+        assert!(limits.check_dimensions(&header(20, 50)).is_ok());
+        assert!(limits.check_dimensions(&header(101, 1)).is_err());
+        assert!(limits.check_dimensions(&header(1, 51)).is_err());
+        assert!(limits.check_dimensions(&header(100, 50)).is_err());
+    }
+}
@@ -1,35 +1,36 @@
-
-
-
 mod args;
-mod chunk;
-mod chunk_type;
 mod commands;
-mod png;
 
-use args::Cli;
+use args::{Cli, Command};
 use clap::Parser;
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-
-    /// Name of the person to greet
-    #[arg(short, long)]
-    name: String,
-
-    #[arg(short, long, default_value_t = 1)]
-    count:u8
-}
-
 fn main() -> Result<()> {
-
-    let args = Cli::parse();
-
-    
-
-    Ok(())
+    let cli = Cli::parse();
+    let limits = cli.limits.to_decode_limits();
+    if cli.top_level_option.is_some() {
+        eprintln!("warning: --top-level-option is deprecated and ignored");
+    }
+
+    match &cli.command {
+        Command::Encode(args) => commands::encode(args, limits),
+        Command::Decode(args) => commands::decode(args, limits),
+        Command::Remove(args) => commands::remove(args, limits),
+        Command::Print(args) => commands::print(args, limits),
+        Command::Strip(args) => commands::strip(args, limits),
+        Command::ExtractFrames(args) => commands::extract_frames(args, limits),
+        Command::Animate(args) => commands::animate(args, limits),
+        Command::Hide(args) => commands::hide(args, limits),
+        Command::Reveal(args) => commands::reveal(args, limits),
+        Command::Capacity(args) => commands::capacity(args, limits),
+        Command::Keygen(args) => commands::keygen(args),
+        Command::Sign(args) => commands::sign(args, limits),
+        Command::Verify(args) => commands::verify(args, limits),
+        Command::Hash(args) => commands::hash(args, limits),
+        Command::Inspect(args) => commands::inspect(args, limits),
+        Command::Trailer(args) => commands::trailer(args, limits),
+        Command::Repair(args) => commands::repair(args, limits),
+    }
 }
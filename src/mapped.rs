@@ -0,0 +1,272 @@
+use crate::chunk::ChunkRef;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::ImageHeader;
+use crate::image::{self, RgbaImage};
+use crate::limits::DecodeLimits;
+use crate::png::{self, Png, PngRef};
+
+use memmap2::Mmap;
+
+use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
+
+/// Where a chunk sits in a mapped file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkEntry {
+    /// Offset of the chunk's length field from the start of the file.
+    pub offset: usize,
+    pub length: u32,
+    pub chunk_type: ChunkType,
+}
+
+impl ChunkEntry {
+    /// Byte range of the chunk data within the file.
+    pub fn data_range(&self) -> Range<usize> {
+        self.offset + 8..self.offset + 8 + self.length as usize
+    }
+
+    /// Byte range of the whole chunk, length and CRC included.
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + 12 + self.length as usize
+    }
+}
+
+/// A PNG file mapped into memory, opened with `Png::open_mmap`. Only the chunk
+/// headers are read when opening, so listing chunks touches a few pages of even a
+/// multi-gigabyte file, and chunk data is read straight from the mapping.
+pub struct MappedPng {
+    map: Mmap,
+    index: Vec<ChunkEntry>,
+    trailer_start: usize,
+    limits: DecodeLimits,
+}
+
+impl MappedPng {
+    /// Maps `path` and indexes its chunks. CRCs are not checked, see `verify_crcs`.
+    /// All of `limits` apply, `max_total_size` included, so a file is accepted or
+    /// refused the same whether a command maps it or reads it.
+    pub fn open<P: AsRef<Path>>(path: P, limits: DecodeLimits) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let size = file.metadata().map_err(|e| e.to_string())?.len();
+        limits.check_total_size(usize::try_from(size).unwrap_or(usize::MAX))?;
+        // SAFETY: the mapping is read-only. Like any tool reading files, we assume
+        // nobody truncates the file while it is open; a rewrite is caught by `chunk`.
+        let map = unsafe { Mmap::map(&file) }.map_err(|e| e.to_string())?;
+
+        let png = PngRef::parse_lazy(&map, &limits)?;
+        let mut offset = Png::STANDARD_HEADER.len();
+        let index = png
+            .chunks()
+            .iter()
+            .map(|chunk| {
+                let entry = ChunkEntry {
+                    offset,
+                    length: chunk.length(),
+                    chunk_type: chunk.chunk_type().clone(),
+                };
+                offset += chunk.raw().len();
+                entry
+            })
+            .collect();
+
+        Ok(MappedPng {
+            map,
+            index,
+            trailer_start: offset,
+            limits,
+        })
+    }
+
+    /// The whole file.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    pub fn index(&self) -> &[ChunkEntry] {
+        &self.index
+    }
+
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    /// The data of the chunk at `entry`, borrowed from the mapping.
+    pub fn chunk_data(&self, entry: &ChunkEntry) -> &[u8] {
+        &self.map[entry.data_range()]
+    }
+
+    /// The chunk at `entry`. Fails if the file was rewritten since it was indexed.
+    pub fn chunk(&self, entry: &ChunkEntry) -> Result<ChunkRef<'_>, String> {
+        let changed = || {
+            format!(
+                "chunk at offset {} changed since the file was opened",
+                entry.offset
+            )
+        };
+        let (chunk, _) = ChunkRef::parse(&self.map[entry.range()], &DecodeLimits::unlimited())
+            .map_err(|_| changed())?;
+        if chunk.length() != entry.length || chunk.chunk_type() != &entry.chunk_type {
+            return Err(changed());
+        }
+        Ok(chunk)
+    }
+
+    /// Bytes after the IEND chunk.
+    pub fn trailer(&self) -> &[u8] {
+        &self.map[self.trailer_start..]
+    }
+
+    /// Borrows every chunk, for the APIs shared with in-memory files.
+    pub fn png_ref(&self) -> Result<PngRef<'_>, String> {
+        let chunks = self
+            .index
+            .iter()
+            .map(|entry| self.chunk(entry))
+            .collect::<Result<_, _>>()?;
+        Ok(PngRef::from_parts(chunks, self.trailer()))
+    }
+
+    /// Checks every CRC, reading the whole file once through the mapping.
+    pub fn verify_crcs(&self) -> Result<(), String> {
+        self.index
+            .iter()
+            .try_for_each(|entry| self.chunk(entry)?.check_crc())
+    }
+
+    fn chunks_by_type<'a>(&'a self, chunk_type: &'a str) -> impl Iterator<Item = &'a [u8]> {
+        self.index
+            .iter()
+            .filter(move |entry| entry.chunk_type.to_string() == chunk_type)
+            .map(|entry| self.chunk_data(entry))
+    }
+
+    fn chunk_data_by_type<'a>(&'a self, chunk_type: &'a str) -> Option<&'a [u8]> {
+        self.chunks_by_type(chunk_type).next()
+    }
+
+    pub fn image_header(&self) -> Result<ImageHeader, String> {
+        ImageHeader::from_bytes(
+            self.chunk_data_by_type("IHDR")
+                .ok_or("missing IHDR chunk")?,
+        )
+    }
+
+    /// Inflates the image data straight from the IDAT chunks in the mapping, without
+    /// joining the compressed stream.
+    fn decode_raw(&self, header: &ImageHeader) -> Result<Vec<u8>, String> {
+        image::decode_pixel_parts(header, &self.idat_parts(), &self.limits)
+    }
+
+    fn idat_parts(&self) -> Vec<&[u8]> {
+        self.chunks_by_type("IDAT").collect()
+    }
+
+    /// Decodes the image into 8-bit RGBA pixels, like `Png::decode_rgba`.
+    pub fn decode_rgba(&self) -> Result<RgbaImage, String> {
+        let header = self.image_header()?;
+        RgbaImage::from_raw(
+            &header,
+            &self.decode_raw(&header)?,
+            self.chunk_data_by_type("PLTE"),
+            self.chunk_data_by_type("tRNS"),
+        )
+    }
+
+    /// The same hash as `Png::pixel_hash`. Rows are decoded and hashed as they are
+    /// inflated from the mapping, so memory use depends on the width only.
+    pub fn pixel_hash(&self) -> Result<[u8; 32], String> {
+        png::hash_pixels(
+            &self.image_header()?,
+            &self.idat_parts(),
+            self.chunk_data_by_type("PLTE"),
+            self.chunk_data_by_type("tRNS"),
+            &self.limits,
+        )
+    }
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn suite_file(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/conformance")
+            .join(name)
+    }
+
+    #[test]
+    fn test_index_matches_png() {
+        let path = suite_file("rgba8-i-paeth.png");
+        let png = Png::from_file(&path).unwrap();
+        let mapped = Png::open_mmap(&path).unwrap();
+
+        assert_eq!(mapped.as_bytes(), fs::read(&path).unwrap());
+        assert_eq!(mapped.index().len(), png.chunks().len());
+        for (entry, chunk) in mapped.index().iter().zip(png.chunks()) {
+            assert_eq!(&entry.chunk_type, chunk.chunk_type());
+            assert_eq!(mapped.chunk_data(entry), chunk.data());
+            assert_eq!(mapped.chunk(entry).unwrap().to_chunk(), *chunk);
+        }
+        assert!(mapped.verify_crcs().is_ok());
+        assert!(mapped.trailer().is_empty());
+        assert_eq!(
+            mapped.png_ref().unwrap().to_png().as_bytes(),
+            png.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_decode_and_hash_match_png() {
+        for name in ["gray1-n-none.png", "pal4-i-up.png", "gray8-trns.png"] {
+            let path = suite_file(name);
+            let png = Png::from_file(&path).unwrap();
+            let mapped = Png::open_mmap(&path).unwrap();
+
+            assert_eq!(mapped.image_header(), png.image_header());
+            assert_eq!(mapped.decode_rgba(), png.decode_rgba(), "{}", name);
+            assert_eq!(mapped.pixel_hash(), png.pixel_hash(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_invalid_files_are_rejected() {
+        assert!(Png::open_mmap(suite_file("missing.png")).is_err());
+        assert!(Png::open_mmap(suite_file("../conformance.rs")).is_err());
+
+        let mapped = Png::open_mmap(suite_file("x-bad-crc.png")).unwrap();
+        assert!(mapped.verify_crcs().unwrap_err().contains("crc"));
+
+        // The same size limit applies as when the file is read into memory.
+        let path = suite_file("gray1-n-none.png");
+        let limits = DecodeLimits {
+            max_total_size: fs::metadata(&path).unwrap().len() as usize - 1,
+            ..DecodeLimits::default()
+        };
+        let error = Png::open_mmap_with_limits(&path, limits).err().unwrap();
+        assert_eq!(Some(error), Png::from_file_with_limits(&path, limits).err());
+    }
+
+    #[test]
+    fn test_chunk_fails_if_the_file_changed() {
+        let source = fs::read(suite_file("gray1-n-none.png")).unwrap();
+        let path = std::env::temp_dir().join(format!("png-rs-{}-changed.png", std::process::id()));
+        fs::write(&path, &source).unwrap();
+        let mapped = Png::open_mmap(&path).unwrap();
+
+        // Rewrite the IHDR length in place, the mapping sees the new bytes.
+        let mut changed = source.clone();
+        changed[11] = 14;
+        fs::write(&path, &changed).unwrap();
+
+        let result = mapped.chunk(&mapped.index()[0]);
+        fs::remove_file(&path).unwrap();
+        assert!(result.unwrap_err().contains("changed"));
+        assert!(mapped.png_ref().is_err());
+    }
+}
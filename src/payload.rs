@@ -0,0 +1,457 @@
+use crate::limits::DecodeLimits;
+use crate::zlib;
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+
+/// Identifies chunk data that is one part of a message split by `split`.
+pub const MAGIC: [u8; 4] = *b"pngP";
+/// The only part header layout understood by `join`.
+pub const VERSION: u8 = 1;
+
+/// magic, version, u32 message id, u32 sequence number, u32 part count, u64 total
+/// length and u32 CRC-32.
+const HEADER_LEN: usize = 4 + 1 + 4 + 4 + 4 + 8 + 4;
+
+/// Default number of message bytes stored in each part.
+pub const DEFAULT_PART_SIZE: usize = 64 * 1024;
+
+/// Identifies a message compressed by `compress`.
+pub const COMPRESSED_MAGIC: [u8; 4] = *b"pngZ";
+/// Flag byte after `COMPRESSED_MAGIC` marking a zlib stream.
+const FLAG_DEFLATE: u8 = 1;
+
+/// The header written in front of every part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartHeader {
+    /// Random id shared by the parts of one message.
+    pub message_id: u32,
+    pub sequence: u32,
+    pub count: u32,
+    pub total_len: u64,
+    pub checksum: u32,
+}
+
+impl PartHeader {
+    /// Parses the header at the start of a part, returning it with the part's data.
+    pub fn from_bytes(data: &[u8]) -> Result<(Self, &[u8]), String> {
+        if !data.starts_with(&MAGIC) {
+            return Err("data is not a message part".to_string());
+        }
+        if data.len() < HEADER_LEN {
+            return Err("message part is truncated".to_string());
+        }
+        if data[4] != VERSION {
+            return Err(format!("unsupported message part version {}", data[4]));
+        }
+
+        let be_u32 = |offset: usize| {
+            u32::from_be_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+        let header = PartHeader {
+            message_id: be_u32(5),
+            sequence: be_u32(9),
+            count: be_u32(13),
+            total_len: u64::from_be_bytes(data[17..25].try_into().unwrap()),
+            checksum: be_u32(25),
+        };
+        if header.count == 0 || header.sequence >= header.count {
+            return Err(format!(
+                "invalid message part {} of {}",
+                header.sequence, header.count
+            ));
+        }
+        Ok((header, &data[HEADER_LEN..]))
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.message_id.to_be_bytes());
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&self.count.to_be_bytes());
+        bytes.extend_from_slice(&self.total_len.to_be_bytes());
+        bytes.extend_from_slice(&self.checksum.to_be_bytes());
+        bytes
+    }
+}
+
+/// True if `data` starts with a valid part header written by `split`. Other data,
+/// even if it happens to start with `MAGIC`, is not a part.
+pub fn is_part(data: &[u8]) -> bool {
+    PartHeader::from_bytes(data).is_ok()
+}
+
+/// Splits `message` into parts of at most `part_size` bytes, each prefixed with a
+/// random message id, its sequence number, the part count, the total length and a
+/// checksum of the message.
+pub fn split(message: &[u8], part_size: usize) -> Result<Vec<Vec<u8>>, String> {
+    if part_size == 0 {
+        return Err("part size must be at least 1 byte".to_string());
+    }
+    let count = u32::try_from(message.len().div_ceil(part_size).max(1))
+        .map_err(|_| "message needs too many parts, use a larger part size".to_string())?;
+    let checksum = crc::crc32::checksum_ieee(message);
+    let message_id = OsRng.next_u32();
+
+    let mut parts: Vec<&[u8]> = message.chunks(part_size).collect();
+    if parts.is_empty() {
+        parts.push(&[]);
+    }
+
+    Ok(parts
+        .into_iter()
+        .enumerate()
+        .map(|(sequence, data)| {
+            let header = PartHeader {
+                message_id,
+                sequence: sequence as u32,
+                count,
+                total_len: message.len() as u64,
+                checksum,
+            };
+            [header.to_bytes(), data.to_vec()].concat()
+        })
+        .collect())
+}
+
+/// A message put back together by `join`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Joined {
+    pub message: Vec<u8>,
+    /// True if the parts were not stored in sequence order.
+    pub reordered: bool,
+}
+
+/// Reassembles the parts written by `split`, in whatever order they are given.
+/// Fails if a part is missing, duplicated, belongs to another message or the
+/// reassembled message does not match its checksum.
+pub fn join<'a, I>(parts: I) -> Result<Joined, String>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let mut parsed = parts
+        .into_iter()
+        .map(PartHeader::from_bytes)
+        .collect::<Result<Vec<_>, _>>()?;
+    let (first, _) = *parsed.first().ok_or("no message parts found")?;
+
+    if let Some((header, _)) = parsed
+        .iter()
+        .find(|(header, _)| message_key(header) != message_key(&first))
+    {
+        return Err(format!(
+            "message part {} belongs to a different message",
+            header.sequence
+        ));
+    }
+
+    let reordered = parsed
+        .windows(2)
+        .any(|pair| pair[0].0.sequence > pair[1].0.sequence);
+    parsed.sort_by_key(|(header, _)| header.sequence);
+
+    for (expected, (header, _)) in (0..first.count).zip(parsed.iter()) {
+        if header.sequence < expected {
+            return Err(format!("message part {} appears twice", header.sequence));
+        }
+        if header.sequence > expected {
+            return Err(format!(
+                "message part {} of {} is missing",
+                expected, first.count
+            ));
+        }
+    }
+    if parsed.len() < first.count as usize {
+        return Err(format!(
+            "message part {} of {} is missing",
+            parsed.len(),
+            first.count
+        ));
+    }
+    if parsed.len() > first.count as usize {
+        return Err(format!(
+            "message part {} appears twice",
+            parsed[first.count as usize].0.sequence
+        ));
+    }
+
+    let message: Vec<u8> = parsed
+        .iter()
+        .flat_map(|(_, data)| data.iter().copied())
+        .collect();
+    if message.len() as u64 != first.total_len {
+        return Err(format!(
+            "reassembled message is {} bytes, expected {}",
+            message.len(),
+            first.total_len
+        ));
+    }
+    if crc::crc32::checksum_ieee(&message) != first.checksum {
+        return Err("reassembled message does not match its checksum".to_string());
+    }
+
+    Ok(Joined { message, reordered })
+}
+
+/// What the parts of one message have in common.
+fn message_key(header: &PartHeader) -> (u32, u32, u64, u32) {
+    (
+        header.message_id,
+        header.count,
+        header.total_len,
+        header.checksum,
+    )
+}
+
+/// Finds every message in the data of a list of chunks: parts are grouped by message
+/// and joined, and any chunk that is not a part is a message on its own. Returns one
+/// result per message, in the order their first chunk appears, so one damaged
+/// message does not hide the others.
+pub fn join_messages<'a, I>(chunks: I) -> Vec<Result<Joined, String>>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    enum Message<'a> {
+        Whole(&'a [u8]),
+        Parts((u32, u32, u64, u32), Vec<&'a [u8]>),
+    }
+
+    let mut messages: Vec<Message> = Vec::new();
+    for data in chunks {
+        let Ok((header, _)) = PartHeader::from_bytes(data) else {
+            messages.push(Message::Whole(data));
+            continue;
+        };
+        let key = message_key(&header);
+        let group = messages.iter_mut().find_map(|message| match message {
+            Message::Parts(other, parts) if *other == key => Some(parts),
+            _ => None,
+        });
+        match group {
+            Some(parts) => parts.push(data),
+            None => messages.push(Message::Parts(key, vec![data])),
+        }
+    }
+
+    messages
+        .into_iter()
+        .map(|message| match message {
+            Message::Whole(data) => Ok(Joined {
+                message: data.to_vec(),
+                reordered: false,
+            }),
+            Message::Parts(_, parts) => join(parts),
+        })
+        .collect()
+}
+
+/// True if `data` starts with the header written by `compress`.
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.starts_with(&COMPRESSED_MAGIC)
+}
+
+/// Deflates `message` and prefixes it with `COMPRESSED_MAGIC` and a flag byte, so
+/// `decompress` can recognize it.
+pub fn compress(message: &[u8]) -> Vec<u8> {
+    let mut data = COMPRESSED_MAGIC.to_vec();
+    data.push(FLAG_DEFLATE);
+    data.extend_from_slice(&zlib::compress(message));
+    data
+}
+
+/// Inflates data written by `compress`, refusing output over
+/// `limits.max_decompressed_size`.
+pub fn decompress(data: &[u8], limits: &DecodeLimits) -> Result<Vec<u8>, String> {
+    if !is_compressed(data) {
+        return Err("data is not compressed".to_string());
+    }
+    match data.get(COMPRESSED_MAGIC.len()) {
+        Some(&FLAG_DEFLATE) => zlib::decompress(
+            &data[COMPRESSED_MAGIC.len() + 1..],
+            limits.max_decompressed_size,
+        ),
+        Some(flag) => Err(format!("unknown compression flag {}", flag)),
+        None => Err("compressed data is truncated".to_string()),
+    }
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> Vec<u8> {
+        b"This is where your secret message will be!".repeat(5)
+    }
+
+    #[test]
+    fn test_split_join_round_trip() {
+        let parts = split(&message(), 64).unwrap();
+
+        assert_eq!(parts.len(), 4);
+        assert!(parts.iter().all(|part| is_part(part)));
+
+        let joined = join(parts.iter().map(Vec::as_slice)).unwrap();
+        assert_eq!(joined.message, message());
+        assert!(!joined.reordered);
+    }
+
+    #[test]
+    fn test_join_reordered_parts() {
+        let mut parts = split(&message(), 64).unwrap();
+        parts.swap(1, 3);
+
+        let joined = join(parts.iter().map(Vec::as_slice)).unwrap();
+        assert_eq!(joined.message, message());
+        assert!(joined.reordered);
+    }
+
+    #[test]
+    fn test_join_detects_missing_and_duplicate_parts() {
+        let parts = split(&message(), 64).unwrap();
+
+        let missing = [&parts[0][..], &parts[1][..], &parts[3][..]];
+        assert!(join(missing)
+            .unwrap_err()
+            .contains("part 2 of 4 is missing"));
+
+        let last_missing = [&parts[0][..], &parts[1][..], &parts[2][..]];
+        assert!(join(last_missing)
+            .unwrap_err()
+            .contains("part 3 of 4 is missing"));
+
+        let duplicate = [&parts[0][..], &parts[1][..], &parts[1][..], &parts[2][..]];
+        assert!(join(duplicate)
+            .unwrap_err()
+            .contains("part 1 appears twice"));
+    }
+
+    #[test]
+    fn test_join_detects_corruption() {
+        let mut parts = split(&message(), 64).unwrap();
+        let last = parts[1].len() - 1;
+        parts[1][last] ^= 1;
+        assert!(join(parts.iter().map(Vec::as_slice))
+            .unwrap_err()
+            .contains("checksum"));
+
+        let other = split(b"another message", 64).unwrap();
+        let mixed = [&parts[0][..], &other[0][..]];
+        assert!(join(mixed).unwrap_err().contains("different message"));
+    }
+
+    #[test]
+    fn test_split_empty_message() {
+        let parts = split(b"", 64).unwrap();
+
+        assert_eq!(parts.len(), 1);
+        assert_eq!(join([&parts[0][..]]).unwrap().message, b"");
+    }
+
+    #[test]
+    fn test_part_header_rejects_bad_data() {
+        let part = split(b"message", 64).unwrap().remove(0);
+
+        assert!(PartHeader::from_bytes(b"plain text").is_err());
+        assert!(PartHeader::from_bytes(&part[..10]).is_err());
+
+        let mut future_version = part.clone();
+        future_version[4] = 2;
+        assert!(PartHeader::from_bytes(&future_version).is_err());
+
+        let mut out_of_range = part;
+        out_of_range[9..13].copy_from_slice(&5u32.to_be_bytes());
+        assert!(PartHeader::from_bytes(&out_of_range).is_err());
+    }
+
+    #[test]
+    fn test_join_messages_separates_messages() {
+        // Two messages of the same length, split into the same chunk type and
+        // interleaved, plus a plain payload that happens to start with the magic.
+        let first = split(&message(), 64).unwrap();
+        let second = split(&message().to_ascii_uppercase(), 64).unwrap();
+        let plain = b"pngP is not a part header".to_vec();
+        let chunks = [
+            &first[0][..],
+            &second[1][..],
+            &plain[..],
+            &second[0][..],
+            &first[1][..],
+            &first[2][..],
+            &second[2][..],
+            &second[3][..],
+            &first[3][..],
+        ];
+
+        let messages = join_messages(chunks);
+        assert_eq!(messages.len(), 3);
+        let first = messages[0].as_ref().unwrap();
+        assert_eq!(first.message, message());
+        assert!(!first.reordered);
+        let second = messages[1].as_ref().unwrap();
+        assert_eq!(second.message, message().to_ascii_uppercase());
+        assert!(second.reordered);
+        assert_eq!(messages[2].as_ref().unwrap().message, plain);
+    }
+
+    #[test]
+    fn test_join_messages_reports_each_message() {
+        let first = split(&message(), 64).unwrap();
+        let second = split(b"short", 64).unwrap();
+        let chunks = [&first[0][..], &second[0][..], &first[2][..]];
+
+        let messages = join_messages(chunks);
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].as_ref().unwrap_err().contains("missing"));
+        assert_eq!(messages[1].as_ref().unwrap().message, b"short");
+    }
+
+    #[test]
+    fn test_compress_round_trip() {
+        let message = message();
+        let compressed = compress(&message);
+
+        assert!(is_compressed(&compressed));
+        assert!(compressed.len() < message.len());
+        assert_eq!(
+            decompress(&compressed, &DecodeLimits::default()).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn test_decompress_rejects_bad_data() {
+        assert!(decompress(b"plain text", &DecodeLimits::default()).is_err());
+        assert!(decompress(b"pngZ", &DecodeLimits::default()).is_err());
+
+        let mut unknown_flag = compress(b"message");
+        unknown_flag[4] = 9;
+        assert!(decompress(&unknown_flag, &DecodeLimits::default())
+            .unwrap_err()
+            .contains("unknown compression flag"));
+    }
+
+    #[test]
+    fn test_decompress_respects_limits() {
+        let message = message();
+        let compressed = compress(&message);
+        let limits = DecodeLimits {
+            max_decompressed_size: message.len() - 1,
+            ..DecodeLimits::default()
+        };
+
+        assert!(decompress(&compressed, &limits).is_err());
+        assert_eq!(
+            decompress(&compressed, &DecodeLimits::unlimited()).unwrap(),
+            message
+        );
+    }
+}
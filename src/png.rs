@@ -1,31 +1,92 @@
 
 
-use crate::chunk::Chunk;
+use crate::apng::{self, AnimationControl, Frame};
+use crate::chunk::{Chunk, ChunkRef};
 use crate::chunk_type::ChunkType;
+use crate::ihdr::ImageHeader;
+use crate::image::{self, RgbaImage};
+use crate::limits::DecodeLimits;
+use crate::mapped::MappedPng;
+
+use sha2::{Digest, Sha256};
 
 use std::fmt;
+use std::fs;
 use std::io::BufRead;
 use std::io::{BufReader, Read};
 use std::path::Path;
-
+use std::str::FromStr;
 
 pub struct Png {
     chunks: Vec<Chunk>,
+    trailer: Vec<u8>,
+    limits: DecodeLimits,
 }
 
-
 impl Png {
     // Fill in this array with the correct values per the PNG spec
     pub const STANDARD_HEADER: [u8; 8] = [137 ,80, 78 ,71 ,13 ,10, 26 ,10];
 
     /// Creates a `Png` from a list of chunks using the correct header
     pub fn from_chunks(chunks: Vec<Chunk>) -> Self {
-        Png { chunks: chunks }
+        Png { chunks, trailer: Vec::new(), limits: DecodeLimits::default() }
     }
 
     /// Creates a `Png` from a file path
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
-        todo!()
+        Self::from_file_with_limits(path, DecodeLimits::default())
+    }
+
+    /// Creates a `Png` from a file path, checking the file size before reading it.
+    pub fn from_file_with_limits<P: AsRef<Path>>(path: P, limits: DecodeLimits) -> Result<Self, String> {
+        let size = fs::metadata(&path).map_err(|e| e.to_string())?.len();
+        limits.check_total_size(usize::try_from(size).unwrap_or(usize::MAX))?;
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        Self::from_bytes_with_limits(&bytes, limits)
+    }
+
+    /// Memory-maps the file at `path` instead of reading it, for files too large to
+    /// hold in memory. See `MappedPng`. The default `max_total_size` still applies,
+    /// use `open_mmap_with_limits` for larger files.
+    pub fn open_mmap<P: AsRef<Path>>(path: P) -> Result<MappedPng, String> {
+        MappedPng::open(path, DecodeLimits::default())
+    }
+
+    /// Like `open_mmap`, with `limits` instead of the defaults.
+    pub fn open_mmap_with_limits<P: AsRef<Path>>(path: P, limits: DecodeLimits) -> Result<MappedPng, String> {
+        MappedPng::open(path, limits)
+    }
+
+    /// Parses `bytes` like `try_from`, but with `limits` instead of the defaults. The
+    /// limits are kept and also apply when decoding the image data.
+    pub fn from_bytes_with_limits(bytes: &[u8], limits: DecodeLimits) -> Result<Self, String> {
+        limits.check_total_size(bytes.len())?;
+        let mut reader = BufReader::new(bytes);
+
+        let mut header_buf = [0;8];
+
+        reader.read_exact(&mut header_buf).map_err(|_x| "Could not decode header")?;
+
+        if header_buf != Self::STANDARD_HEADER {
+            return Err("Decoded header does not equal expected header".to_string());
+        }
+
+        let mut decoded_chunks = vec![];
+        let mut trailer = vec![];
+
+        // Nothing after IEND is part of the image, keep it aside instead of parsing it.
+        while !reader.fill_buf().unwrap().is_empty() {
+            let chunk = Chunk::read_chunk(&mut reader, &limits)?;
+            let is_end = chunk.chunk_type().to_string() == "IEND";
+            decoded_chunks.push(chunk);
+            limits.check_chunk_count(decoded_chunks.len())?;
+            if is_end {
+                reader.read_to_end(&mut trailer).map_err(|e| e.to_string())?;
+                break;
+            }
+        }
+
+        Ok(Png { chunks: decoded_chunks, trailer, limits })
     }
 
     /// Appends a chunk to the end of this `Png` file's `Chunk` list.
@@ -33,21 +94,87 @@ impl Png {
         self.chunks.push(chunk)
     }
 
+    /// Inserts a chunk right before IEND, or at the end if there is no IEND.
+    pub fn insert_chunk_before_iend(&mut self, chunk: Chunk) {
+        let index = self.chunks.iter().position(|chunk| chunk.chunk_type().to_string() == "IEND").unwrap_or(self.chunks.len());
+        self.chunks.insert(index, chunk)
+    }
+
     /// Searches for a `Chunk` with the specified `chunk_type` and removes the first
     /// matching `Chunk` from this `Png` list of chunks.
     pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk, String> {
         self.chunks.iter().position(|chunk| chunk.chunk_type().to_string() == chunk_type).map(|index| self.chunks.remove(index)).ok_or_else(|| "non-existent chunk".into())
     }
 
+    /// Removes every `Chunk` matching `predicate` from this `Png`, keeping the
+    /// remaining chunks in their original order. Returns the removed chunks.
+    pub fn remove_chunks<F>(&mut self, predicate: F) -> Vec<Chunk>
+    where
+        F: FnMut(&Chunk) -> bool,
+    {
+        let (removed, kept) = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .partition(predicate);
+        self.chunks = kept;
+        removed
+    }
+
+    /// Removes the `Chunk` at `index` in this `Png` list of chunks.
+    pub fn remove_chunk_at(&mut self, index: usize) -> Result<Chunk, String> {
+        if index >= self.chunks.len() {
+            return Err(format!(
+                "chunk index {} out of range, png has {} chunks",
+                index,
+                self.chunks.len()
+            ));
+        }
+        Ok(self.chunks.remove(index))
+    }
+
+    /// Removes every ancillary `Chunk` whose type satisfies `should_remove`.
+    /// Critical chunks are never removed. Returns the removed chunks.
+    pub fn strip_chunks<F>(&mut self, mut should_remove: F) -> Vec<Chunk>
+    where
+        F: FnMut(&ChunkType) -> bool,
+    {
+        self.remove_chunks(|chunk| {
+            !chunk.chunk_type().is_critical() && should_remove(chunk.chunk_type())
+        })
+    }
+
     /// The header of this PNG.
     pub fn header(&self) -> &[u8; 8] {
-        return &Png::STANDARD_HEADER;
+        &Png::STANDARD_HEADER
     }
 
     /// Lists the `Chunk`s stored in this `Png`
     pub fn chunks(&self) -> &[Chunk] {
-        println!("{}", self);
-        return &self.chunks;
+        &self.chunks
+    }
+
+    /// Bytes found after the IEND chunk, such as an appended archive. Empty for most files.
+    pub fn trailer(&self) -> &[u8] {
+        &self.trailer
+    }
+
+    /// Replaces the bytes written after the IEND chunk.
+    pub fn set_trailer(&mut self, trailer: Vec<u8>) {
+        self.trailer = trailer
+    }
+
+    /// Removes and returns the bytes after the IEND chunk.
+    pub fn take_trailer(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.trailer)
+    }
+
+    /// The limits applied when decoding this `Png`'s image data.
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    /// Replaces the limits used by `decode_rgba`, `pixel_hash` and `render_frames`.
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.limits = limits
     }
 
     /// Searches for a `Chunk` with the specified `chunk_type` and returns the first
@@ -56,42 +183,121 @@ impl Png {
         self.chunks.iter().find(|chunk| chunk.chunk_type().to_string() == chunk_type)
     }
 
+    /// Iterates over every `Chunk` with the specified `chunk_type`, in file order.
+    pub fn chunks_by_type<'a>(&'a self, chunk_type: &'a str) -> impl Iterator<Item = &'a Chunk> {
+        self.chunks
+            .iter()
+            .filter(move |chunk| chunk.chunk_type().to_string() == chunk_type)
+    }
+
+    /// Parses the IHDR chunk of this `Png`.
+    pub fn image_header(&self) -> Result<ImageHeader, String> {
+        let chunk = self.chunk_by_type("IHDR").ok_or("missing IHDR chunk")?;
+        ImageHeader::from_bytes(chunk.data())
+    }
+
+    /// Parses the acTL chunk, returning `None` if this is not an animated PNG.
+    pub fn animation_control(&self) -> Result<Option<AnimationControl>, String> {
+        self.chunk_by_type("acTL")
+            .map(|chunk| AnimationControl::from_bytes(chunk.data()))
+            .transpose()
+    }
+
+    /// Splits an animated PNG into its frames, validating the fcTL/fdAT sequence numbers
+    /// and that every frame fits inside the canvas.
+    pub fn frames(&self) -> Result<Vec<Frame>, String> {
+        apng::frames(&self.image_header()?, &self.chunks)
+    }
+
+    /// Renders every frame of an animated PNG onto a full-size RGBA canvas.
+    pub fn render_frames(&self) -> Result<Vec<RgbaImage>, String> {
+        apng::render(
+            &self.image_header()?,
+            &self.frames()?,
+            self.chunk_by_type("PLTE").map(Chunk::data),
+            self.chunk_by_type("tRNS").map(Chunk::data),
+            &self.limits,
+        )
+    }
+
+    /// The concatenated data of every IDAT chunk, i.e. the compressed image stream.
+    pub fn idat_data(&self) -> Vec<u8> {
+        self.chunks_by_type("IDAT")
+            .flat_map(|chunk| chunk.data().iter().copied())
+            .collect()
+    }
+
+    /// Decodes the image into 8-bit RGBA pixels.
+    pub fn decode_rgba(&self) -> Result<RgbaImage, String> {
+        let header = self.image_header()?;
+        let raw = image::decode_pixels(&header, &self.idat_data(), &self.limits)?;
+        RgbaImage::from_raw(
+            &header,
+            &raw,
+            self.chunk_by_type("PLTE").map(Chunk::data),
+            self.chunk_by_type("tRNS").map(Chunk::data),
+        )
+    }
+
+    /// SHA-256 of the decoded pixels, independent of compression, filtering,
+    /// interlacing, bit depth, color type and chunk layout. Only the default image of
+    /// an animated PNG is hashed. The image is decoded a few rows at a time, so this
+    /// needs little memory even for huge images.
+    pub fn pixel_hash(&self) -> Result<[u8; 32], String> {
+        let parts: Vec<&[u8]> = self.chunks_by_type("IDAT").map(Chunk::data).collect();
+        hash_pixels(
+            &self.image_header()?,
+            &parts,
+            self.chunk_by_type("PLTE").map(Chunk::data),
+            self.chunk_by_type("tRNS").map(Chunk::data),
+            &self.limits,
+        )
+    }
+
+    /// Replaces IHDR with `header` and every IDAT chunk with a single IDAT holding
+    /// `compressed`, placed where the first IDAT was.
+    pub fn set_image_data(&mut self, header: ImageHeader, compressed: Vec<u8>) -> Result<(), String> {
+        let ihdr = self.chunks.iter().position(|chunk| chunk.chunk_type().to_string() == "IHDR").ok_or("missing IHDR chunk")?;
+        let idat = self.chunks.iter().position(|chunk| chunk.chunk_type().to_string() == "IDAT").ok_or("missing IDAT chunk")?;
+
+        self.chunks[ihdr] = header.to_chunk();
+        self.chunks[idat] = Chunk::new(ChunkType::from_str("IDAT")?, compressed);
+        let mut index = 0;
+        self.chunks.retain(|chunk| {
+            index += 1;
+            index - 1 == idat || chunk.chunk_type().to_string() != "IDAT"
+        });
+        Ok(())
+    }
+
     /// Returns this `Png` as a byte sequence.
-    /// These bytes will contain the header followed by the bytes of all of the chunks.
+    /// These bytes will contain the header followed by the bytes of all of the chunks
+    /// and the trailer, so a parsed file is written back unchanged.
     pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes_out = self.as_bytes_without_trailer();
+        bytes_out.extend_from_slice(&self.trailer);
+        bytes_out
+    }
+
+    /// Returns the header and chunks of this `Png`, leaving out the trailer.
+    pub fn as_bytes_without_trailer(&self) -> Vec<u8> {
         let mut bytes_out = vec![];
-        bytes_out.extend_from_slice(&Self::STANDARD_HEADER);
+        bytes_out.extend_from_slice(self.header());
 
         for chunk in self.chunks.iter() {
             let mut chunk_bytes = chunk.as_bytes();
             bytes_out.append(&mut chunk_bytes);
         }
 
-        return bytes_out;
+        bytes_out
     }
 }
 
 impl TryFrom<&[u8]> for Png {
-    type Error = String; // how does this work?
+    type Error = String;
 
     fn try_from(bytes: &[u8]) -> Result<Png, Self::Error> {
-        let mut reader = BufReader::new(bytes);
-
-        let mut header_buf = [0;8];
-
-        reader.read_exact(&mut header_buf).map_err(|_x| "Could not decode header")?;
-
-        if header_buf != Self::STANDARD_HEADER {
-            return Err("Decoded header does not equal expected header".to_string());
-        }
-
-        let mut decoded_chunks = vec![];
-
-        while !reader.fill_buf().unwrap().is_empty() {
-            decoded_chunks.push(Chunk::read_chunk(&mut reader)?);
-        }
-
-        Ok(Png { chunks: decoded_chunks })
+        Png::from_bytes_with_limits(bytes, DecodeLimits::default())
     }
 }
 
@@ -105,16 +311,155 @@ impl fmt::Display for Png {
         write!(f, "End chunks")?;
         Ok(())
     }
+}
 
-    
+/// Hashes the image data in `parts` for `Png::pixel_hash`, streaming the decoded rows
+/// into the hasher.
+pub(crate) fn hash_pixels(
+    header: &ImageHeader,
+    parts: &[&[u8]],
+    palette: Option<&[u8]>,
+    transparency: Option<&[u8]>,
+    limits: &DecodeLimits,
+) -> Result<[u8; 32], String> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"png-rs pixels v1");
+    hasher.update(header.width.to_be_bytes());
+    hasher.update(header.height.to_be_bytes());
+
+    let mut bytes = Vec::new();
+    image::for_each_rgba16_row(header, parts, palette, transparency, limits, |row| {
+        bytes.clear();
+        bytes.extend(row.iter().flatten().flat_map(|value| value.to_be_bytes()));
+        hasher.update(&bytes);
+    })?;
+    Ok(hasher.finalize().into())
 }
 
+/// A PNG borrowed from a buffer, usually a whole file read or mapped into memory.
+/// Chunks point into the buffer, so listing or hashing a large file copies nothing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PngRef<'a> {
+    chunks: Vec<ChunkRef<'a>>,
+    trailer: &'a [u8],
+}
+
+impl<'a> PngRef<'a> {
+    /// Parses `bytes`, rejecting any chunk whose CRC does not match.
+    pub fn parse(bytes: &'a [u8], limits: &DecodeLimits) -> Result<Self, String> {
+        let png = Self::parse_lazy(bytes, limits)?;
+        png.verify_crcs()?;
+        Ok(png)
+    }
+
+    /// Parses `bytes` without checking CRCs, which then costs nothing until
+    /// `verify_crcs` or `ChunkRef::check_crc` is called.
+    pub fn parse_lazy(bytes: &'a [u8], limits: &DecodeLimits) -> Result<Self, String> {
+        limits.check_total_size(bytes.len())?;
+        if bytes.len() < 8 {
+            return Err("Could not decode header".to_string());
+        }
+        if bytes[..8] != Png::STANDARD_HEADER {
+            return Err("Decoded header does not equal expected header".to_string());
+        }
+
+        let mut chunks = vec![];
+        let mut offset = 8;
+        while offset < bytes.len() {
+            let (chunk, used) = ChunkRef::parse(&bytes[offset..], limits)?;
+            let is_end = chunk.chunk_type().to_string() == "IEND";
+            chunks.push(chunk);
+            limits.check_chunk_count(chunks.len())?;
+            offset += used;
+            if is_end {
+                break;
+            }
+        }
+
+        Ok(PngRef { chunks, trailer: &bytes[offset..] })
+    }
+
+    pub(crate) fn from_parts(chunks: Vec<ChunkRef<'a>>, trailer: &'a [u8]) -> Self {
+        PngRef { chunks, trailer }
+    }
+
+    pub fn verify_crcs(&self) -> Result<(), String> {
+        self.chunks.iter().try_for_each(ChunkRef::check_crc)
+    }
+
+    pub fn chunks(&self) -> &[ChunkRef<'a>] {
+        &self.chunks
+    }
+
+    /// Bytes after the IEND chunk.
+    pub fn trailer(&self) -> &'a [u8] {
+        self.trailer
+    }
+
+    /// Parses the trailer as chunks, the way older versions of `encode` appended
+    /// messages after IEND. Fails if the trailer holds anything else.
+    pub fn trailer_chunks(&self, limits: &DecodeLimits) -> Result<Vec<ChunkRef<'a>>, String> {
+        let mut chunks = Vec::new();
+        let mut rest = self.trailer;
+        while !rest.is_empty() {
+            limits.check_chunk_count(chunks.len() + 1)?;
+            let (chunk, used) = ChunkRef::parse(rest, limits)?;
+            chunk.check_crc()?;
+            chunks.push(chunk);
+            rest = &rest[used..];
+        }
+        Ok(chunks)
+    }
+
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&ChunkRef<'a>> {
+        self.chunks.iter().find(|chunk| chunk.chunk_type().to_string() == chunk_type)
+    }
+
+    pub fn chunks_by_type<'b>(&'b self, chunk_type: &'b str) -> impl Iterator<Item = &'b ChunkRef<'a>> {
+        self.chunks
+            .iter()
+            .filter(move |chunk| chunk.chunk_type().to_string() == chunk_type)
+    }
 
+    pub fn image_header(&self) -> Result<ImageHeader, String> {
+        let chunk = self.chunk_by_type("IHDR").ok_or("missing IHDR chunk")?;
+        ImageHeader::from_bytes(chunk.data())
+    }
 
+    pub fn animation_control(&self) -> Result<Option<AnimationControl>, String> {
+        self.chunk_by_type("acTL")
+            .map(|chunk| AnimationControl::from_bytes(chunk.data()))
+            .transpose()
+    }
 
+    /// Splits an animated PNG into its frames, like `Png::frames`. The frames hold
+    /// copies of their image data.
+    pub fn frames(&self) -> Result<Vec<Frame>, String> {
+        apng::frames(&self.image_header()?, &self.chunks)
+    }
+
+    /// The concatenated data of every IDAT chunk. This is the one accessor that copies.
+    pub fn idat_data(&self) -> Vec<u8> {
+        self.chunks_by_type("IDAT")
+            .flat_map(|chunk| chunk.data().iter().copied())
+            .collect()
+    }
 
+    /// Copies every chunk and the trailer into an owned `Png`.
+    pub fn to_png(&self) -> Png {
+        let mut png = Png::from_chunks(self.chunks.iter().map(ChunkRef::to_chunk).collect());
+        png.set_trailer(self.trailer.to_vec());
+        png
+    }
+}
 
+impl<'a> TryFrom<&'a [u8]> for PngRef<'a> {
+    type Error = String;
 
+    fn try_from(bytes: &'a [u8]) -> Result<PngRef<'a>, Self::Error> {
+        PngRef::parse(bytes, &DecodeLimits::default())
+    }
+}
 
 // Unit Tests
 
@@ -122,18 +467,19 @@ impl fmt::Display for Png {
 mod tests {
     use super::*;
     use crate::chunk_type::ChunkType;
-    use crate::chunk::Chunk;
+    use crate::ihdr::ColorType;
+    use crate::chunk::{strategies, Chunk};
+    use proptest::prelude::*;
+    use proptest::sample::Index;
     use std::str::FromStr;
     use std::convert::TryFrom;
 
     fn testing_chunks() -> Vec<Chunk> {
-        let mut chunks = Vec::new();
-
-        chunks.push(chunk_from_strings("FrSt", "I am the first chunk").unwrap());
-        chunks.push(chunk_from_strings("miDl", "I am another chunk").unwrap());
-        chunks.push(chunk_from_strings("LASt", "I am the last chunk").unwrap());
-
-        chunks
+        vec![
+            chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
+            chunk_from_strings("miDl", "I am another chunk").unwrap(),
+            chunk_from_strings("LASt", "I am the last chunk").unwrap(),
+        ]
     }
 
     fn testing_png() -> Png {
@@ -215,7 +561,6 @@ mod tests {
         assert!(png.is_err());
     }
 
-
     #[test]
     fn test_list_chunks() {
         let png = testing_png();
@@ -232,6 +577,20 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "I am a second middle chunk").unwrap());
+
+        let data: Vec<String> = png
+            .chunks_by_type("miDl")
+            .map(|chunk| chunk.data_as_string().unwrap())
+            .collect();
+
+        assert_eq!(data, ["I am another chunk", "I am a second middle chunk"]);
+        assert_eq!(png.chunks_by_type("NoNe").count(), 0);
+    }
+
     #[test]
     fn test_append_chunk() {
         let mut png = testing_png();
@@ -241,6 +600,15 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    #[test]
+    fn test_insert_chunk_before_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk_before_iend(chunk_from_strings("teSt", "Message").unwrap());
+
+        let types: Vec<String> = png.chunks().iter().rev().take(2).map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IEND", "teSt"]);
+    }
+
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
@@ -251,12 +619,261 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_remove_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "Another").unwrap());
+
+        let removed = png.remove_chunks(|chunk| chunk.chunk_type().to_string() == "TeSt");
+
+        assert_eq!(removed.len(), 2);
+        assert_eq!(&removed[0].data_as_string().unwrap(), "Message");
+        assert_eq!(&removed[1].data_as_string().unwrap(), "Another");
+        assert!(png.chunk_by_type("TeSt").is_none());
+        assert_eq!(png.chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_remove_chunk_at() {
+        let mut png = testing_png();
+
+        let removed = png.remove_chunk_at(1).unwrap();
+
+        assert_eq!(&removed.chunk_type().to_string(), "miDl");
+        assert_eq!(png.chunks().len(), 2);
+        assert!(png.remove_chunk_at(2).is_err());
+    }
+
+    #[test]
+    fn test_strip_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("teSt", "Message").unwrap());
+        png.append_chunk(chunk_from_strings("teSt", "Another").unwrap());
+
+        let removed = png.strip_chunks(|_| true);
+
+        assert_eq!(removed.len(), 3);
+        let remaining: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(remaining, ["FrSt", "LASt"]);
+    }
+
+    #[test]
+    fn test_strip_chunks_never_removes_critical() {
+        let mut png = testing_png();
+
+        let removed = png.strip_chunks(|chunk_type| chunk_type.to_string() == "FrSt");
+
+        assert!(removed.is_empty());
+        assert!(png.chunk_by_type("FrSt").is_some());
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
         assert!(png.is_ok());
     }
 
+    #[test]
+    fn test_decode_rgba() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+
+        let image = png.decode_rgba().unwrap();
+
+        assert_eq!((image.width, image.height), (50, 50));
+        assert_eq!(image.to_png().decode_rgba().unwrap(), image);
+    }
+
+    #[test]
+    fn test_pixel_hash_ignores_encoding() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let hash = png.pixel_hash().unwrap();
+
+        // Re-encoded with different filters and compression, without metadata chunks.
+        let reencoded = png.decode_rgba().unwrap().to_png();
+        assert_ne!(reencoded.as_bytes(), png.as_bytes());
+        assert_eq!(reencoded.pixel_hash().unwrap(), hash);
+
+        let mut edited = png.decode_rgba().unwrap();
+        edited.pixels[0] ^= 1;
+        assert_ne!(edited.to_png().pixel_hash().unwrap(), hash);
+    }
+
+    #[test]
+    fn test_pixel_hash_matches_across_bit_depths() {
+        let header = ImageHeader { width: 2, height: 1, bit_depth: 8, color_type: ColorType::Grayscale, interlaced: false };
+        let wide = ImageHeader { bit_depth: 16, color_type: ColorType::Rgb, ..header };
+        let narrow = Png::from_chunks(vec![
+            header.to_chunk(),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), image::encode_pixels(&header, &[0x12, 0xff]).unwrap()),
+        ]);
+        let wide = Png::from_chunks(vec![
+            wide.to_chunk(),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), image::encode_pixels(&wide, &[0x12, 0x12, 0x12, 0x12, 0x12, 0x12, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).unwrap()),
+        ]);
+
+        assert_eq!(narrow.pixel_hash().unwrap(), wide.pixel_hash().unwrap());
+    }
+
+    #[test]
+    fn test_set_image_data() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let header = png.image_header().unwrap();
+        let mut raw = image::decode_pixels(&header, &png.idat_data(), png.limits()).unwrap();
+        raw[0] ^= 1;
+
+        let chunk_types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        png.set_image_data(header, image::encode_pixels(&header, &raw).unwrap()).unwrap();
+
+        let new_types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(new_types, chunk_types);
+        assert_eq!(image::decode_pixels(&header, &png.idat_data(), png.limits()).unwrap(), raw);
+    }
+
+    #[test]
+    fn test_trailer_after_iend() {
+        let bytes = [&PNG_FILE[..], b"PK\x03\x04 appended archive"].concat();
+
+        let mut png = Png::try_from(bytes.as_slice()).unwrap();
+
+        assert_eq!(png.chunks().len(), 7);
+        assert_eq!(png.trailer(), b"PK\x03\x04 appended archive");
+        assert_eq!(png.as_bytes(), bytes);
+        assert_eq!(png.as_bytes_without_trailer(), PNG_FILE);
+
+        assert_eq!(png.take_trailer(), b"PK\x03\x04 appended archive");
+        assert_eq!(png.as_bytes(), PNG_FILE);
+    }
+
+    #[test]
+    fn test_trailer_chunks() {
+        // Older versions of `encode` appended chunks after IEND.
+        let appended = chunk_from_strings("ruSt", "Message").unwrap();
+        let bytes = [&PNG_FILE[..], &appended.as_bytes()].concat();
+
+        let png = PngRef::parse(&bytes, &DecodeLimits::default()).unwrap();
+        let chunks = png.trailer_chunks(&DecodeLimits::default()).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].to_chunk(), appended);
+
+        let garbage = [&PNG_FILE[..], b"garbage"].concat();
+        let png = PngRef::parse(&garbage, &DecodeLimits::default()).unwrap();
+        assert!(png.trailer_chunks(&DecodeLimits::default()).is_err());
+    }
+
+    #[test]
+    fn test_garbage_after_iend_is_not_parsed() {
+        let bytes = [&PNG_FILE[..], &[0xff; 3]].concat();
+
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+
+        assert_eq!(png.trailer(), [0xff; 3]);
+    }
+
+    #[test]
+    fn test_decode_limits() {
+        let limits = DecodeLimits { max_chunks: 6, ..DecodeLimits::default() };
+        assert!(Png::from_bytes_with_limits(&PNG_FILE, limits).is_err());
+
+        let limits = DecodeLimits { max_total_size: PNG_FILE.len() - 1, ..DecodeLimits::default() };
+        assert!(Png::from_bytes_with_limits(&PNG_FILE, limits).is_err());
+
+        let limits = DecodeLimits { max_pixels: 10, ..DecodeLimits::default() };
+        let png = Png::from_bytes_with_limits(&PNG_FILE, limits).unwrap();
+        assert!(png.decode_rgba().is_err());
+        assert!(Png::try_from(&PNG_FILE[..]).unwrap().decode_rgba().is_ok());
+    }
+
+    fn chunk_bytes(png: &Png) -> Vec<Vec<u8>> {
+        png.chunks().iter().map(Chunk::as_bytes).collect()
+    }
+
+    proptest! {
+        #[test]
+        fn test_png_round_trip(
+            chunks in prop::collection::vec(strategies::chunk_before_iend(), 0..8),
+            end in any::<bool>(),
+            trailer in prop::collection::vec(any::<u8>(), 0..32),
+        ) {
+            let mut png = Png::from_chunks(chunks);
+            if end {
+                png.append_chunk(chunk_from_strings("IEND", "").unwrap());
+                png.set_trailer(trailer);
+            }
+            let bytes = png.as_bytes();
+
+            let parsed = Png::try_from(bytes.as_slice()).unwrap();
+
+            prop_assert_eq!(parsed.chunks(), png.chunks());
+            prop_assert_eq!(parsed.trailer(), png.trailer());
+            prop_assert_eq!(parsed.as_bytes(), bytes);
+        }
+
+        #[test]
+        fn test_remove_chunk_keeps_order(
+            chunks in prop::collection::vec(strategies::chunk(), 1..8),
+            index in any::<Index>(),
+        ) {
+            let chunk_type = chunks[index.index(chunks.len())].chunk_type().to_string();
+            let mut png = Png::from_chunks(chunks);
+            let mut expected = chunk_bytes(&png);
+            // Only the first chunk of the type goes.
+            let first = png.chunks().iter().position(|chunk| chunk.chunk_type().to_string() == chunk_type).unwrap();
+
+            let removed = png.remove_chunk(&chunk_type).unwrap();
+
+            prop_assert_eq!(removed.as_bytes(), expected.remove(first));
+            prop_assert_eq!(chunk_bytes(&png), expected);
+        }
+
+        #[test]
+        fn test_append_chunk_keeps_order(
+            chunks in prop::collection::vec(strategies::chunk(), 0..8),
+            chunk in strategies::chunk(),
+        ) {
+            let mut png = Png::from_chunks(chunks);
+            let mut expected = chunk_bytes(&png);
+            expected.push(chunk.as_bytes());
+
+            png.append_chunk(chunk);
+
+            prop_assert_eq!(chunk_bytes(&png), expected);
+        }
+    }
+
+    #[test]
+    fn test_png_ref_matches_png() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let png_ref = PngRef::try_from(&PNG_FILE[..]).unwrap();
+
+        assert_eq!(png_ref.chunks().len(), png.chunks().len());
+        assert_eq!(png_ref.image_header(), png.image_header());
+        assert_eq!(png_ref.idat_data(), png.idat_data());
+        assert_eq!(png_ref.to_png().as_bytes(), PNG_FILE.to_vec());
+
+        let with_trailer = [&PNG_FILE[..], b"trailer"].concat();
+        let png_ref = PngRef::try_from(with_trailer.as_slice()).unwrap();
+        assert_eq!(png_ref.trailer(), b"trailer");
+        assert_eq!(png_ref.to_png().as_bytes(), with_trailer);
+    }
+
+    #[test]
+    fn test_png_ref_checks_crcs_eagerly_unless_lazy() {
+        let mut bytes = PNG_FILE.to_vec();
+        // Flip a bit in the IHDR data.
+        bytes[20] ^= 1;
+        let limits = DecodeLimits::default();
+
+        assert!(PngRef::parse(&bytes, &limits).unwrap_err().contains("crc"));
+        let png_ref = PngRef::parse_lazy(&bytes, &limits).unwrap();
+        assert!(png_ref.verify_crcs().is_err());
+        assert!(png_ref.chunks()[1].check_crc().is_ok());
+    }
+
     #[test]
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
@@ -0,0 +1,350 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::image;
+use crate::limits::DecodeLimits;
+use crate::png::Png;
+use crate::registry;
+use crate::zlib;
+
+use std::str::FromStr;
+
+/// Unregistered chunks longer than this are not CRC-checked while resynchronizing,
+/// so they are never picked as the next chunk after garbage.
+const MAX_RESYNC_CRC_LEN: usize = 1 << 20;
+/// Most bytes CRC-checked while resynchronizing over a whole file. Garbage full of
+/// plausible headers would otherwise cost a CRC over every one of them.
+const RESYNC_CRC_BUDGET: usize = 16 << 20;
+
+/// A `Png` loaded by `parse_lenient`, with everything that was wrong with the file.
+pub struct Recovered {
+    pub png: Png,
+    pub problems: Vec<String>,
+}
+
+/// Loads as much of a damaged PNG as possible. CRC mismatches are recorded instead of
+/// failing, garbage between chunks is skipped by searching for the next plausible
+/// chunk and a truncated final chunk keeps whatever data is present. The chunks get
+/// freshly computed CRCs, so writing the result out repairs them. Chunks over the
+/// size limit count as garbage and parsing stops at the size and chunk count limits.
+pub fn parse_lenient(bytes: &[u8], limits: DecodeLimits) -> Recovered {
+    let mut problems = Vec::new();
+    let mut chunks = Vec::new();
+
+    if bytes.len() > limits.max_total_size {
+        problems.push(format!(
+            "ignored {} bytes past the size limit",
+            bytes.len() - limits.max_total_size
+        ));
+    }
+    let bytes = &bytes[..bytes.len().min(limits.max_total_size)];
+
+    let mut crc_budget = RESYNC_CRC_BUDGET;
+    let mut offset = Png::STANDARD_HEADER.len();
+    if !bytes.starts_with(&Png::STANDARD_HEADER) {
+        problems.push("invalid PNG signature".to_string());
+        offset = match find_chunk(bytes, 0, &limits, &mut crc_budget) {
+            Some(start) => start,
+            None => bytes.len(),
+        };
+    }
+
+    let mut trailer = Vec::new();
+    while offset < bytes.len() {
+        if chunks.len() == limits.max_chunks {
+            problems.push(format!("stopped after {} chunks", limits.max_chunks));
+            break;
+        }
+        if !is_plausible(bytes, offset, &limits) {
+            match find_chunk(bytes, offset + 1, &limits, &mut crc_budget) {
+                Some(next) => {
+                    problems.push(format!(
+                        "skipped {} bytes of garbage at offset {}",
+                        next - offset,
+                        offset
+                    ));
+                    offset = next;
+                }
+                None => {
+                    problems.push(format!(
+                        "ignored {} bytes of garbage at offset {}",
+                        bytes.len() - offset,
+                        offset
+                    ));
+                    break;
+                }
+            }
+            continue;
+        }
+
+        let length = read_u32(bytes, offset) as usize;
+        let type_bytes: [u8; 4] = bytes[offset + 4..offset + 8].try_into().unwrap();
+        let chunk_type = ChunkType::new_lenient(type_bytes).unwrap();
+        let data_start = offset + 8;
+        let end = data_start + length + 4;
+
+        if end > bytes.len() {
+            let data = &bytes[data_start.min(bytes.len())..bytes.len().min(data_start + length)];
+            problems.push(format!(
+                "{} chunk at offset {} is truncated, kept {} of {} bytes",
+                chunk_type,
+                offset,
+                data.len(),
+                length
+            ));
+            chunks.push(Chunk::new(chunk_type, data.to_vec()));
+            break;
+        }
+
+        let chunk = Chunk::new(chunk_type, bytes[data_start..data_start + length].to_vec());
+        if chunk.crc() != read_u32(bytes, data_start + length) {
+            problems.push(format!(
+                "CRC mismatch in {} chunk at offset {}",
+                chunk.chunk_type(),
+                offset
+            ));
+        }
+
+        let is_end = chunk.chunk_type().to_string() == "IEND";
+        chunks.push(chunk);
+        offset = end;
+        if is_end {
+            trailer = bytes[offset..].to_vec();
+            break;
+        }
+    }
+
+    let mut png = Png::from_chunks(chunks);
+    png.set_trailer(trailer);
+    png.set_limits(limits);
+    Recovered { png, problems }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// True if a chunk header with a letter-only type and a length within `limits` starts
+/// at `offset`.
+fn is_plausible(bytes: &[u8], offset: usize, limits: &DecodeLimits) -> bool {
+    bytes.len() >= offset + 8
+        && bytes[offset + 4..offset + 8]
+            .iter()
+            .all(u8::is_ascii_alphabetic)
+        && limits.check_chunk_length(read_u32(bytes, offset)).is_ok()
+}
+
+/// Searches from `start` for the next chunk header worth resynchronizing on: a
+/// plausible header whose type is registered or whose CRC checks out. CRCs are only
+/// checked for chunks up to `MAX_RESYNC_CRC_LEN` long and while `crc_budget` lasts,
+/// after that only registered types are found.
+fn find_chunk(
+    bytes: &[u8],
+    start: usize,
+    limits: &DecodeLimits,
+    crc_budget: &mut usize,
+) -> Option<usize> {
+    (start..bytes.len().saturating_sub(7)).find(|&offset| {
+        if !is_plausible(bytes, offset, limits) {
+            return false;
+        }
+        let chunk_type =
+            ChunkType::new_lenient(bytes[offset + 4..offset + 8].try_into().unwrap()).unwrap();
+        if registry::lookup(&chunk_type).is_some() {
+            return true;
+        }
+
+        let length = read_u32(bytes, offset) as usize;
+        let crc_end = offset + 12 + length;
+        if length > MAX_RESYNC_CRC_LEN || crc_end > bytes.len() || length + 4 > *crc_budget {
+            return false;
+        }
+        *crc_budget -= length + 4;
+        crc::crc32::checksum_ieee(&bytes[offset + 4..crc_end - 4]) == read_u32(bytes, crc_end - 4)
+    })
+}
+
+/// Fixes what `parse_lenient` could not: re-adds a missing IEND and rebuilds damaged
+/// image data from whatever scanlines can still be inflated, filling the rest with
+/// zeros. Returns a description of every change.
+pub fn repair(png: &mut Png) -> Result<Vec<String>, String> {
+    let mut changes = Vec::new();
+
+    if png.chunk_by_type("IEND").is_none() {
+        png.append_chunk(Chunk::new(ChunkType::from_str("IEND")?, Vec::new()));
+        changes.push("added missing IEND chunk".to_string());
+    }
+
+    let header = png.image_header()?;
+    let compressed = png.idat_data();
+    if compressed.is_empty() {
+        return Err("no image data to salvage".to_string());
+    }
+
+    let limits = *png.limits();
+    limits.check_dimensions(&header)?;
+    let expected = image::filtered_len(&header);
+    if expected > limits.max_decompressed_size {
+        return Err(format!(
+            "image data inflates to {} bytes, more than the limit of {}",
+            expected, limits.max_decompressed_size
+        ));
+    }
+
+    let (mut inflated, error) = zlib::decompress_partial(&compressed, limits.max_decompressed_size);
+    if error.is_none() && inflated.len() >= expected {
+        return Ok(changes);
+    }
+
+    let recovered = inflated.len().min(expected);
+    inflated.resize(expected, 0);
+    let raw = image::unfilter_image(&header, &inflated)?;
+    png.set_image_data(header, image::encode_pixels(&header, &raw)?)?;
+    changes.push(format!(
+        "rebuilt image data from {} of {} bytes ({:.1}%){}",
+        recovered,
+        expected,
+        100.0 * recovered as f64 / expected as f64,
+        error.map_or(String::new(), |e| format!(", {}", e))
+    ));
+
+    Ok(changes)
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::{ColorType, ImageHeader};
+
+    fn image_png() -> Png {
+        let header = ImageHeader {
+            width: 32,
+            height: 32,
+            bit_depth: 8,
+            color_type: ColorType::Rgb,
+            interlaced: false,
+        };
+        let mut state = 1u32;
+        let raw: Vec<u8> = (0..32 * 32 * 3)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        Png::from_chunks(vec![
+            header.to_chunk(),
+            Chunk::new(
+                ChunkType::from_str("tEXt").unwrap(),
+                b"Comment\0hello".to_vec(),
+            ),
+            Chunk::new(
+                ChunkType::from_str("IDAT").unwrap(),
+                image::encode_pixels(&header, &raw).unwrap(),
+            ),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ])
+    }
+
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_file_has_no_problems() {
+        let bytes = image_png().as_bytes();
+
+        let recovered = parse_lenient(&bytes, DecodeLimits::default());
+
+        assert!(recovered.problems.is_empty());
+        assert_eq!(recovered.png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_crc_mismatch_is_recorded_and_fixed() {
+        let original = image_png().as_bytes();
+        let mut bytes = original.clone();
+        // Last byte of the tEXt CRC: signature, IHDR (25 bytes), then 12 + 13 bytes.
+        bytes[8 + 25 + 24] ^= 1;
+        assert!(Png::try_from(bytes.as_slice()).is_err());
+
+        let recovered = parse_lenient(&bytes, DecodeLimits::default());
+
+        assert_eq!(recovered.problems.len(), 1);
+        assert!(recovered.problems[0].contains("CRC mismatch in tEXt"));
+        assert_eq!(recovered.png.as_bytes(), original);
+    }
+
+    #[test]
+    fn test_resynchronizes_after_garbage() {
+        let original = image_png().as_bytes();
+        let text_start = 8 + 25;
+        let bytes = [
+            &original[..text_start],
+            b"\xff\x00garbage",
+            &original[text_start..],
+        ]
+        .concat();
+
+        let recovered = parse_lenient(&bytes, DecodeLimits::default());
+
+        assert_eq!(recovered.problems.len(), 1, "{:?}", recovered.problems);
+        assert!(recovered.problems[0].contains("skipped 9 bytes of garbage"));
+        assert_eq!(
+            chunk_types(&recovered.png),
+            ["IHDR", "tEXt", "IDAT", "IEND"]
+        );
+    }
+
+    #[test]
+    fn test_resynchronizing_is_bounded() {
+        // Every eighth offset holds a plausible header of an unregistered 512 KiB
+        // chunk with a bad CRC, which used to cost a CRC over 512 KiB each.
+        let header = [&0x80000u32.to_be_bytes()[..], b"zzzz"].concat();
+        let garbage = header.repeat((4 << 20) / header.len());
+        let bytes = [&image_png().as_bytes()[..8 + 25], b"\xff", &garbage].concat();
+
+        let start = std::time::Instant::now();
+        let recovered = parse_lenient(&bytes, DecodeLimits::default());
+
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+        assert_eq!(chunk_types(&recovered.png), ["IHDR"]);
+        assert!(recovered.problems[0].contains("ignored"));
+    }
+
+    #[test]
+    fn test_repair_truncated_file() {
+        let original = image_png().as_bytes();
+        // Cut the file in the middle of the IDAT chunk, losing IEND too.
+        let bytes = &original[..original.len() - 12 - 1000];
+
+        let mut recovered = parse_lenient(bytes, DecodeLimits::default());
+        assert!(recovered.problems[0].contains("IDAT chunk"));
+        assert!(recovered.problems[0].contains("truncated"));
+
+        let changes = repair(&mut recovered.png).unwrap();
+        assert_eq!(changes.len(), 2, "{:?}", changes);
+        assert!(changes[0].contains("IEND"));
+        assert!(changes[1].contains("rebuilt image data"));
+
+        let repaired = Png::try_from(recovered.png.as_bytes().as_slice()).unwrap();
+        assert_eq!(chunk_types(&repaired), ["IHDR", "tEXt", "IDAT", "IEND"]);
+
+        // The rows that survived decode to the original pixels.
+        let expected = image_png().decode_rgba().unwrap();
+        let actual = repaired.decode_rgba().unwrap();
+        assert_eq!(actual.pixel(0, 0), expected.pixel(0, 0));
+        assert_eq!(actual.pixel(31, 31), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_repair_leaves_intact_image_data() {
+        let mut png = image_png();
+
+        assert!(repair(&mut png).unwrap().is_empty());
+        assert_eq!(png.as_bytes(), image_png().as_bytes());
+    }
+}
@@ -0,0 +1,253 @@
+use crate::chunk::ChunkView;
+use crate::chunk_type::ChunkType;
+
+/// Where a chunk type is defined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// The PNG specification itself.
+    Png,
+    /// The Animated PNG extension.
+    Apng,
+    /// Extensions registered with the PNG group.
+    Extension,
+    /// Widely used chunks defined by individual applications.
+    ThirdParty,
+}
+
+/// Where a chunk is allowed to appear relative to the other chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    First,
+    Last,
+    /// Before PLTE and the first IDAT.
+    BeforePlte,
+    /// After PLTE (if present) and before the first IDAT.
+    AfterPlte,
+    BeforeIdat,
+    AfterIdat,
+    Anywhere,
+}
+
+/// Static information about a known chunk type.
+#[derive(Debug)]
+pub struct ChunkInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub origin: Origin,
+    pub placement: Placement,
+    /// Whether more than one chunk of this type may appear.
+    pub multiple: bool,
+}
+
+const fn info(
+    name: &'static str,
+    description: &'static str,
+    origin: Origin,
+    placement: Placement,
+    multiple: bool,
+) -> ChunkInfo {
+    ChunkInfo {
+        name,
+        description,
+        origin,
+        placement,
+        multiple,
+    }
+}
+
+use Origin::*;
+use Placement::*;
+
+/// Every chunk type this crate knows about.
+#[rustfmt::skip]
+pub const KNOWN_CHUNKS: &[ChunkInfo] = &[
+    info("IHDR", "Image header", Png, First, false),
+    info("PLTE", "Palette", Png, BeforeIdat, false),
+    info("IDAT", "Image data", Png, Anywhere, true),
+    info("IEND", "Image trailer", Png, Last, false),
+    info("cHRM", "Primary chromaticities and white point", Png, BeforePlte, false),
+    info("gAMA", "Image gamma", Png, BeforePlte, false),
+    info("iCCP", "Embedded ICC profile", Png, BeforePlte, false),
+    info("sBIT", "Significant bits", Png, BeforePlte, false),
+    info("sRGB", "Standard RGB color space", Png, BeforePlte, false),
+    info("cICP", "Coding-independent code points", Png, BeforePlte, false),
+    info("mDCV", "Mastering display color volume", Png, BeforePlte, false),
+    info("cLLI", "Content light level information", Png, BeforePlte, false),
+    info("bKGD", "Background color", Png, AfterPlte, false),
+    info("hIST", "Palette histogram", Png, AfterPlte, false),
+    info("tRNS", "Transparency", Png, AfterPlte, false),
+    info("eXIf", "Exchangeable image file (Exif) profile", Png, BeforeIdat, false),
+    info("pHYs", "Physical pixel dimensions", Png, BeforeIdat, false),
+    info("sPLT", "Suggested palette", Png, BeforeIdat, true),
+    info("tIME", "Last modification time", Png, Anywhere, false),
+    info("iTXt", "International textual data", Png, Anywhere, true),
+    info("tEXt", "Textual data", Png, Anywhere, true),
+    info("zTXt", "Compressed textual data", Png, Anywhere, true),
+    info("acTL", "Animation control", Apng, BeforeIdat, false),
+    info("fcTL", "Frame control", Apng, Anywhere, true),
+    info("fdAT", "Frame data", Apng, AfterIdat, true),
+    info("oFFs", "Image offset", Extension, BeforeIdat, false),
+    info("pCAL", "Calibration of pixel values", Extension, BeforeIdat, false),
+    info("sCAL", "Physical scale of image subject", Extension, BeforeIdat, false),
+    info("gIFg", "GIF graphic control extension", Extension, Anywhere, true),
+    info("gIFt", "GIF plain text extension (deprecated)", Extension, Anywhere, true),
+    info("gIFx", "GIF application extension", Extension, Anywhere, true),
+    info("sTER", "Indicator of stereo image", Extension, BeforeIdat, false),
+    info("dSIG", "Digital signature", Extension, Anywhere, true),
+    info("fRAc", "Fractal image parameters", Extension, Anywhere, true),
+    info("vpAg", "Virtual page (ImageMagick)", ThirdParty, BeforeIdat, false),
+    info("caNv", "Virtual canvas (ImageMagick)", ThirdParty, BeforeIdat, false),
+    info("orNT", "Orientation (ImageMagick)", ThirdParty, BeforeIdat, false),
+    info("iDOT", "Parallel decoding offsets (Apple)", ThirdParty, BeforeIdat, false),
+    info("CgBI", "Premultiplied BGRA image (Apple)", ThirdParty, First, false),
+];
+
+/// Looks up the registry entry for `chunk_type`, if it is known.
+pub fn lookup(chunk_type: &ChunkType) -> Option<&'static ChunkInfo> {
+    KNOWN_CHUNKS
+        .iter()
+        .find(|info| info.name.as_bytes() == chunk_type.bytes())
+}
+
+/// Checks `chunks` against the ordering and multiplicity rules of the known chunk
+/// types and flags unknown critical chunks. Returns a description of each problem.
+pub fn validate<C: ChunkView>(chunks: &[C]) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let position = |name: &str| {
+        chunks
+            .iter()
+            .position(|chunk| chunk.chunk_type().bytes() == name.as_bytes())
+    };
+    let first_idat = position("IDAT");
+    let last_idat = chunks
+        .iter()
+        .rposition(|chunk| chunk.chunk_type().bytes() == *b"IDAT");
+    let plte = position("PLTE");
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        let chunk_type = chunk.chunk_type();
+        let info = match lookup(chunk_type) {
+            Some(info) => info,
+            None => {
+                if chunk_type.is_critical() {
+                    problems.push(format!(
+                        "unknown critical chunk {} at index {}",
+                        chunk_type, index
+                    ));
+                }
+                continue;
+            }
+        };
+
+        let before = |limit: Option<usize>| limit.is_none_or(|limit| index < limit);
+        let misplaced = match info.placement {
+            // Apple's CgBI chunk is the one exception that precedes IHDR.
+            First => index != 0 && !(index == 1 && chunks[0].chunk_type().bytes() == *b"CgBI"),
+            Last => index != chunks.len() - 1,
+            BeforePlte => !before(plte) || !before(first_idat),
+            AfterPlte => plte.is_some_and(|plte| index < plte) || !before(first_idat),
+            BeforeIdat => !before(first_idat),
+            AfterIdat => first_idat.is_none_or(|first_idat| index < first_idat),
+            Anywhere => false,
+        };
+        if misplaced {
+            problems.push(format!(
+                "{} at index {} violates ordering rule {:?}",
+                chunk_type, index, info.placement
+            ));
+        }
+
+        let is_first_occurrence = position(info.name) == Some(index);
+        if !info.multiple && !is_first_occurrence {
+            problems.push(format!("duplicate {} chunk at index {}", chunk_type, index));
+        }
+    }
+
+    if position("IHDR").is_none() {
+        problems.push("missing IHDR chunk".to_string());
+    }
+    if first_idat.is_none() {
+        problems.push("missing IDAT chunk".to_string());
+    }
+    if position("IEND").is_none() {
+        problems.push("missing IEND chunk".to_string());
+    }
+    if let (Some(first), Some(last)) = (first_idat, last_idat) {
+        if chunks[first..=last]
+            .iter()
+            .any(|chunk| chunk.chunk_type().bytes() != *b"IDAT")
+        {
+            problems.push("IDAT chunks are not consecutive".to_string());
+        }
+    }
+
+    problems
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use std::str::FromStr;
+
+    fn chunk(name: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str_lenient(name).unwrap(), Vec::new())
+    }
+
+    fn chunks(names: &[&str]) -> Vec<Chunk> {
+        names.iter().map(|name| chunk(name)).collect()
+    }
+
+    #[test]
+    fn test_lookup() {
+        let info = lookup(&ChunkType::from_str("pHYs").unwrap()).unwrap();
+        assert_eq!(info.description, "Physical pixel dimensions");
+        assert_eq!(info.placement, Placement::BeforeIdat);
+
+        assert!(lookup(&ChunkType::from_str("ruSt").unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_registry_names_are_unique_and_valid() {
+        for (index, info) in KNOWN_CHUNKS.iter().enumerate() {
+            assert!(ChunkType::from_str_lenient(info.name).is_ok());
+            assert!(KNOWN_CHUNKS[..index]
+                .iter()
+                .all(|other| other.name != info.name));
+        }
+    }
+
+    #[test]
+    fn test_validate_valid_layout() {
+        let chunks = chunks(&[
+            "IHDR", "gAMA", "PLTE", "tRNS", "IDAT", "IDAT", "tEXt", "IEND",
+        ]);
+        assert!(validate(&chunks).is_empty());
+    }
+
+    #[test]
+    fn test_validate_flags_unknown_critical() {
+        let chunks = chunks(&["IHDR", "IDAT", "RuSt", "ruSt", "IEND"]);
+        let problems = validate(&chunks);
+        assert_eq!(problems, ["unknown critical chunk RuSt at index 2"]);
+    }
+
+    #[test]
+    fn test_validate_flags_ordering_and_duplicates() {
+        let chunks = chunks(&[
+            "IHDR", "PLTE", "gAMA", "IDAT", "pHYs", "IDAT", "IEND", "IHDR",
+        ]);
+        let problems = validate(&chunks);
+        assert_eq!(problems.len(), 6);
+        assert!(problems.iter().any(|problem| problem.starts_with("gAMA")));
+        assert!(problems.iter().any(|problem| problem.starts_with("pHYs")));
+        assert!(problems.iter().any(|problem| problem.starts_with("IEND")));
+        assert!(problems
+            .iter()
+            .any(|problem| problem.starts_with("duplicate IHDR")));
+        assert!(problems.contains(&"IDAT chunks are not consecutive".to_string()));
+    }
+}
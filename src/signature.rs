@@ -0,0 +1,338 @@
+use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+
+use std::str::FromStr;
+
+/// Private, ancillary, safe-to-copy chunk holding an embedded signature. Editors may
+/// copy it into a modified image, where `verify` then reports the change.
+pub const CHUNK_TYPE: &str = "siGn";
+/// The only signature layout understood by `Signature::from_bytes`.
+pub const VERSION: u8 = 1;
+
+/// Separates our digests from other uses of SHA-256 over the same bytes.
+const DOMAIN: &[u8] = b"png-rs signature v1";
+const KEY_LEN: usize = 32;
+const DIGEST_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
+
+/// Ancillary chunks that change how the image renders: transparency and animation.
+/// They are signed along with the critical chunks.
+const PIXEL_CHUNKS: [&str; 4] = ["tRNS", "acTL", "fcTL", "fdAT"];
+
+/// A signed record of the digests of the chunks that make up an image's pixels and of
+/// the ancillary chunk types chosen at signing time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub public_key: [u8; KEY_LEN],
+    pub protected: Vec<ChunkType>,
+    pub pixel_digest: [u8; DIGEST_LEN],
+    pub ancillary_digest: [u8; DIGEST_LEN],
+    signature: [u8; SIGNATURE_LEN],
+}
+
+/// What `Signature::verify` found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Verification {
+    /// The critical chunks and `PIXEL_CHUNKS`, and so the pixels, are unchanged.
+    pub pixels_unchanged: bool,
+    /// The protected ancillary chunks are unchanged.
+    pub ancillary_unchanged: bool,
+}
+
+impl Verification {
+    pub fn is_valid(&self) -> bool {
+        self.pixels_unchanged && self.ancillary_unchanged
+    }
+}
+
+/// Hashes the type, length and data of `chunks` in file order.
+fn digest<'a>(chunks: impl Iterator<Item = &'a Chunk>) -> [u8; DIGEST_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.update(DOMAIN);
+    for chunk in chunks {
+        hasher.update(chunk.chunk_type().bytes());
+        hasher.update(chunk.length().to_be_bytes());
+        hasher.update(chunk.data());
+    }
+    hasher.finalize().into()
+}
+
+/// True for the chunks that are always signed because they change the rendered
+/// image: the critical chunks and `PIXEL_CHUNKS`.
+pub fn guards_pixels(chunk_type: &ChunkType) -> bool {
+    chunk_type.is_critical() || PIXEL_CHUNKS.contains(&chunk_type.to_string().as_str())
+}
+
+/// Digests of the chunks that guard the pixels and of the other ancillary chunks whose
+/// type is in `protected`. Signature chunks are never included.
+pub fn digests(chunks: &[Chunk], protected: &[ChunkType]) -> ([u8; DIGEST_LEN], [u8; DIGEST_LEN]) {
+    let pixels = digest(
+        chunks
+            .iter()
+            .filter(|chunk| guards_pixels(chunk.chunk_type())),
+    );
+    let ancillary = digest(chunks.iter().filter(|chunk| {
+        let chunk_type = chunk.chunk_type();
+        !guards_pixels(chunk_type)
+            && chunk_type.to_string() != CHUNK_TYPE
+            && protected.contains(chunk_type)
+    }));
+    (pixels, ancillary)
+}
+
+impl Signature {
+    /// Signs the digests of `chunks` with `key`.
+    pub fn sign(
+        chunks: &[Chunk],
+        protected: &[ChunkType],
+        key: &SigningKey,
+    ) -> Result<Self, String> {
+        if protected.len() > u8::MAX as usize {
+            return Err(format!("at most {} chunk types can be protected", u8::MAX));
+        }
+        let (pixel_digest, ancillary_digest) = digests(chunks, protected);
+        let mut signature = Signature {
+            public_key: key.verifying_key().to_bytes(),
+            protected: protected.to_vec(),
+            pixel_digest,
+            ancillary_digest,
+            signature: [0; SIGNATURE_LEN],
+        };
+        signature.signature = key.sign(&signature.signed_bytes()).to_bytes();
+        Ok(signature)
+    }
+
+    /// Checks the signature itself, failing if the record was forged or modified,
+    /// then compares the stored digests with those of `chunks`.
+    pub fn verify(&self, chunks: &[Chunk]) -> Result<Verification, String> {
+        let key = VerifyingKey::from_bytes(&self.public_key)
+            .map_err(|_| "signature holds an invalid public key".to_string())?;
+        key.verify(
+            &self.signed_bytes(),
+            &ed25519_dalek::Signature::from_bytes(&self.signature),
+        )
+        .map_err(|_| "signature does not match its contents".to_string())?;
+
+        let (pixels, ancillary) = digests(chunks, &self.protected);
+        Ok(Verification {
+            pixels_unchanged: pixels == self.pixel_digest,
+            ancillary_unchanged: ancillary == self.ancillary_digest,
+        })
+    }
+
+    /// Everything but the signature, i.e. the bytes that are signed.
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![VERSION];
+        bytes.extend_from_slice(&self.public_key);
+        bytes.push(self.protected.len() as u8);
+        for chunk_type in self.protected.iter() {
+            bytes.extend_from_slice(&chunk_type.bytes());
+        }
+        bytes.extend_from_slice(&self.pixel_digest);
+        bytes.extend_from_slice(&self.ancillary_digest);
+        bytes
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.signed_bytes(), self.signature.to_vec()].concat()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let truncated = || "signature is truncated".to_string();
+        let take = |offset: usize, len: usize| data.get(offset..offset + len).ok_or_else(truncated);
+
+        if *data.first().ok_or_else(truncated)? != VERSION {
+            return Err(format!("unsupported signature version {}", data[0]));
+        }
+        let public_key = take(1, KEY_LEN)?.try_into().unwrap();
+        let count = *take(1 + KEY_LEN, 1)?.first().unwrap() as usize;
+
+        let mut offset = 2 + KEY_LEN;
+        let mut protected = Vec::with_capacity(count);
+        for _ in 0..count {
+            let bytes: [u8; 4] = take(offset, 4)?.try_into().unwrap();
+            protected.push(ChunkType::try_from(bytes).map_err(|e| e.to_string())?);
+            offset += 4;
+        }
+
+        let pixel_digest = take(offset, DIGEST_LEN)?.try_into().unwrap();
+        let ancillary_digest = take(offset + DIGEST_LEN, DIGEST_LEN)?.try_into().unwrap();
+        let signature = take(offset + 2 * DIGEST_LEN, SIGNATURE_LEN)?
+            .try_into()
+            .unwrap();
+        if data.len() != offset + 2 * DIGEST_LEN + SIGNATURE_LEN {
+            return Err("signature has trailing bytes".to_string());
+        }
+
+        Ok(Signature {
+            public_key,
+            protected,
+            pixel_digest,
+            ancillary_digest,
+            signature,
+        })
+    }
+
+    /// Builds the `siGn` chunk holding this signature.
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(ChunkType::from_str(CHUNK_TYPE).unwrap(), self.to_bytes())
+    }
+}
+
+/// Lowercase hex, used for key files and fingerprints.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Parses the lowercase or uppercase hex written by `to_hex`, ignoring surrounding
+/// whitespace.
+pub fn from_hex(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim();
+    if !text.is_ascii() {
+        return Err("hex string contains non-ASCII characters".to_string());
+    }
+    if !text.len().is_multiple_of(2) {
+        return Err("hex string has an odd number of digits".to_string());
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&text[i..i + 2], 16)
+                .map_err(|_| format!("invalid hex digits {:?}", &text[i..i + 2]))
+        })
+        .collect()
+}
+
+/// Reads a signing key stored as 32 hex-encoded bytes.
+pub fn signing_key_from_hex(text: &str) -> Result<SigningKey, String> {
+    let bytes: [u8; KEY_LEN] = from_hex(text)?
+        .try_into()
+        .map_err(|_| "signing key must be 32 bytes".to_string())?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// Reads a public key stored as 32 hex-encoded bytes.
+pub fn public_key_from_hex(text: &str) -> Result<[u8; KEY_LEN], String> {
+    from_hex(text)?
+        .try_into()
+        .map_err(|_| "public key must be 32 bytes".to_string())
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> SigningKey {
+        SigningKey::from_bytes(&[42; KEY_LEN])
+    }
+
+    fn chunk(chunk_type: &str, data: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.into())
+    }
+
+    fn chunks() -> Vec<Chunk> {
+        vec![
+            chunk("IHDR", "header"),
+            chunk("tEXt", "Author\0Me"),
+            chunk("tIME", "today"),
+            chunk("IDAT", "pixels"),
+            chunk("IEND", ""),
+        ]
+    }
+
+    fn protected() -> Vec<ChunkType> {
+        vec![ChunkType::from_str("tEXt").unwrap()]
+    }
+
+    #[test]
+    fn test_sign_verify_round_trip() {
+        let signature = Signature::sign(&chunks(), &protected(), &key()).unwrap();
+
+        let parsed = Signature::from_bytes(&signature.to_bytes()).unwrap();
+        assert_eq!(parsed, signature);
+        assert_eq!(parsed.public_key, key().verifying_key().to_bytes());
+
+        let mut signed = chunks();
+        signed.insert(4, signature.to_chunk());
+        assert!(parsed.verify(&signed).unwrap().is_valid());
+    }
+
+    #[test]
+    fn test_verify_reports_what_changed() {
+        let signature = Signature::sign(&chunks(), &protected(), &key()).unwrap();
+
+        let mut pixels = chunks();
+        pixels[3] = chunk("IDAT", "edited");
+        let result = signature.verify(&pixels).unwrap();
+        assert!(!result.pixels_unchanged);
+        assert!(result.ancillary_unchanged);
+
+        let mut metadata = chunks();
+        metadata[1] = chunk("tEXt", "Author\0You");
+        let result = signature.verify(&metadata).unwrap();
+        assert!(result.pixels_unchanged);
+        assert!(!result.ancillary_unchanged);
+
+        // Transparency and animation chunks change the pixels without being critical.
+        for edit in [chunk("tRNS", "alpha"), chunk("fcTL", "frame")] {
+            let mut rendering = chunks();
+            rendering.insert(3, edit);
+            assert!(!signature.verify(&rendering).unwrap().pixels_unchanged);
+        }
+
+        // Unprotected chunks can change freely.
+        let mut unprotected = chunks();
+        unprotected.remove(2);
+        assert!(signature.verify(&unprotected).unwrap().is_valid());
+    }
+
+    #[test]
+    fn test_verify_rejects_forged_records() {
+        let signature = Signature::sign(&chunks(), &protected(), &key()).unwrap();
+
+        let mut edited = chunks();
+        edited[3] = chunk("IDAT", "edited");
+        let mut forged = signature.clone();
+        forged.pixel_digest = digests(&edited, &protected()).0;
+        assert!(forged.verify(&edited).is_err());
+
+        // Dropping a type from the protected list is also caught.
+        let mut unprotected = signature;
+        unprotected.protected.clear();
+        assert!(unprotected.verify(&chunks()).is_err());
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_bad_data() {
+        let bytes = Signature::sign(&chunks(), &protected(), &key())
+            .unwrap()
+            .to_bytes();
+
+        assert!(Signature::from_bytes(&[]).is_err());
+        assert!(Signature::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Signature::from_bytes(&[bytes.clone(), vec![0]].concat()).is_err());
+
+        let mut future_version = bytes;
+        future_version[0] = 2;
+        assert!(Signature::from_bytes(&future_version)
+            .unwrap_err()
+            .contains("unsupported signature version"));
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        let key = key();
+        let hex = to_hex(&key.to_bytes());
+
+        assert_eq!(hex.len(), 64);
+        assert_eq!(signing_key_from_hex(&format!("{}\n", hex)).unwrap(), key);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+        assert!(public_key_from_hex("abcd").is_err());
+    }
+}
@@ -0,0 +1,420 @@
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+
+use crate::crypto::KdfParams;
+use crate::ihdr::{ColorType, ImageHeader};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Length of the message prefix: a u32 message length and a CRC-32 of the message.
+const PREFIX_LEN: usize = 8;
+/// Fixed salt for turning a key into a seed; the key alone decides the embedding order.
+const SEED_SALT: &[u8] = b"png-rs lsb order";
+
+/// A color channel that message bits may be written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+impl Channel {
+    /// Position of this channel's sample within a pixel. Grayscale images use their gray
+    /// sample for every color channel.
+    fn sample_index(self, color_type: ColorType) -> Option<usize> {
+        match (color_type, self) {
+            (ColorType::Rgb | ColorType::Rgba, Channel::Red) => Some(0),
+            (ColorType::Rgb | ColorType::Rgba, Channel::Green) => Some(1),
+            (ColorType::Rgb | ColorType::Rgba, Channel::Blue) => Some(2),
+            (ColorType::Rgba, Channel::Alpha) => Some(3),
+            (ColorType::GrayscaleAlpha, Channel::Alpha) => Some(1),
+            (ColorType::Grayscale | ColorType::GrayscaleAlpha, _) => Some(0),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "r" | "red" => Ok(Channel::Red),
+            "g" | "green" => Ok(Channel::Green),
+            "b" | "blue" => Ok(Channel::Blue),
+            "a" | "alpha" => Ok(Channel::Alpha),
+            _ => Err(format!("unknown channel {}, expected r, g, b or a", s)),
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Channel::Red => "red",
+            Channel::Green => "green",
+            Channel::Blue => "blue",
+            Channel::Alpha => "alpha",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Which channels and bit planes carry message bits. Bit plane 0 is the least
+/// significant bit of a sample.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    pub channels: Vec<Channel>,
+    pub bit_planes: Vec<u8>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            channels: vec![Channel::Red, Channel::Green, Channel::Blue],
+            bit_planes: vec![0],
+        }
+    }
+}
+
+/// Maps slot numbers to bit positions in non-interlaced raw scanlines.
+struct Slots {
+    samples: Vec<usize>,
+    bit_planes: Vec<u8>,
+    bytes_per_sample: usize,
+    bytes_per_pixel: usize,
+    len: usize,
+}
+
+impl Slots {
+    fn new(header: &ImageHeader, layout: &Layout) -> Result<Self, String> {
+        if header.color_type == ColorType::Indexed {
+            return Err("LSB embedding does not support indexed images".to_string());
+        }
+        if header.bit_depth < 8 {
+            return Err(format!(
+                "LSB embedding needs 8 or 16-bit samples, image is {}-bit",
+                header.bit_depth
+            ));
+        }
+
+        let mut samples = Vec::new();
+        for channel in layout.channels.iter() {
+            let sample = channel.sample_index(header.color_type).ok_or_else(|| {
+                format!("{:?} images have no {} channel", header.color_type, channel)
+            })?;
+            if !samples.contains(&sample) {
+                samples.push(sample);
+            }
+        }
+
+        let mut bit_planes = layout.bit_planes.clone();
+        bit_planes.sort_unstable();
+        bit_planes.dedup();
+        if let Some(&plane) = bit_planes.iter().find(|&&plane| plane >= header.bit_depth) {
+            return Err(format!(
+                "bit plane {} does not exist in {}-bit samples",
+                plane, header.bit_depth
+            ));
+        }
+        if samples.is_empty() || bit_planes.is_empty() {
+            return Err("no channels or bit planes selected".to_string());
+        }
+
+        let pixels = header.width as usize * header.height as usize;
+        let bytes_per_sample = header.bit_depth as usize / 8;
+        Ok(Slots {
+            len: pixels * samples.len() * bit_planes.len(),
+            samples,
+            bit_planes,
+            bytes_per_sample,
+            bytes_per_pixel: header.bits_per_pixel() / 8,
+        })
+    }
+
+    /// The byte offset and bit mask of slot `index`.
+    fn position(&self, index: usize) -> (usize, u8) {
+        let plane = self.bit_planes[index % self.bit_planes.len()] as usize;
+        let index = index / self.bit_planes.len();
+        let sample = self.samples[index % self.samples.len()];
+        let pixel = index / self.samples.len();
+
+        // Samples are big-endian, so the low bits of a 16-bit sample are in its last byte.
+        let byte = pixel * self.bytes_per_pixel
+            + sample * self.bytes_per_sample
+            + (self.bytes_per_sample - 1 - plane / 8);
+        (byte, 1 << (plane % 8))
+    }
+}
+
+/// Yields slot numbers in a key-dependent order without repeats. This is a lazy
+/// Fisher-Yates shuffle that only remembers the swapped entries, so embedding a short
+/// message in a large image stays cheap.
+struct Order {
+    keystream: ChaCha20,
+    swapped: HashMap<usize, usize>,
+    taken: usize,
+    len: usize,
+}
+
+impl Order {
+    fn new(seed: &[u8; 32], len: usize) -> Self {
+        Order {
+            keystream: ChaCha20::new(seed.into(), &[0; 12].into()),
+            swapped: HashMap::new(),
+            taken: 0,
+            len,
+        }
+    }
+
+    /// Uniform random number below `bound`, rejecting values that would bias the result.
+    fn below(&mut self, bound: usize) -> usize {
+        let bound = bound as u64;
+        let rejected = (u64::MAX % bound + 1) % bound;
+        loop {
+            let mut bytes = [0; 8];
+            self.keystream.apply_keystream(&mut bytes);
+            let value = u64::from_le_bytes(bytes);
+            if value <= u64::MAX - rejected {
+                return (value % bound) as usize;
+            }
+        }
+    }
+}
+
+impl Iterator for Order {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.taken == self.len {
+            return None;
+        }
+
+        let pick = self.taken + self.below(self.len - self.taken);
+        let current = self.swapped.remove(&self.taken).unwrap_or(self.taken);
+        let value = if pick == self.taken {
+            current
+        } else {
+            self.swapped.insert(pick, current).unwrap_or(pick)
+        };
+        self.taken += 1;
+        Some(value)
+    }
+}
+
+/// Derives the 32-byte seed that decides the embedding order from a user supplied key.
+pub fn derive_seed(key: &[u8]) -> Result<[u8; 32], String> {
+    KdfParams::default().derive_key(key, SEED_SALT)
+}
+
+/// Number of message bytes that fit in an image with this layout.
+pub fn capacity(header: &ImageHeader, layout: &Layout) -> Result<usize, String> {
+    Ok((Slots::new(header, layout)?.len / 8).saturating_sub(PREFIX_LEN))
+}
+
+/// Writes `message` into the selected bit planes of `raw`, the non-interlaced scanlines
+/// returned by `image::decode_pixels`. Bits are spread over the image in an order
+/// decided by `seed`.
+pub fn embed(
+    header: &ImageHeader,
+    raw: &mut [u8],
+    layout: &Layout,
+    seed: &[u8; 32],
+    message: &[u8],
+) -> Result<(), String> {
+    let slots = Slots::new(header, layout)?;
+    let capacity = (slots.len / 8).saturating_sub(PREFIX_LEN);
+    if message.len() > capacity {
+        return Err(format!(
+            "message is {} bytes but the image only has room for {}",
+            message.len(),
+            capacity
+        ));
+    }
+
+    let mut payload = Vec::with_capacity(PREFIX_LEN + message.len());
+    payload.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    payload.extend_from_slice(&crc::crc32::checksum_ieee(message).to_be_bytes());
+    payload.extend_from_slice(message);
+
+    let bits = payload
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1 == 1));
+    for (slot, bit) in Order::new(seed, slots.len).zip(bits) {
+        let (byte, mask) = slots.position(slot);
+        if bit {
+            raw[byte] |= mask;
+        } else {
+            raw[byte] &= !mask;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a message written by `embed` with the same layout and seed.
+pub fn extract(
+    header: &ImageHeader,
+    raw: &[u8],
+    layout: &Layout,
+    seed: &[u8; 32],
+) -> Result<Vec<u8>, String> {
+    let slots = Slots::new(header, layout)?;
+    let capacity = (slots.len / 8).saturating_sub(PREFIX_LEN);
+    let not_found = || "no hidden message found, check the key, channels and bit planes";
+
+    let mut order = Order::new(seed, slots.len);
+    let mut read_bytes = |count: usize| -> Vec<u8> {
+        (0..count)
+            .map(|_| {
+                order.by_ref().take(8).fold(0, |byte, slot| {
+                    let (offset, mask) = slots.position(slot);
+                    (byte << 1) | (raw[offset] & mask != 0) as u8
+                })
+            })
+            .collect()
+    };
+
+    let prefix = read_bytes(PREFIX_LEN);
+    let length = u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+    let checksum = u32::from_be_bytes([prefix[4], prefix[5], prefix[6], prefix[7]]);
+    if length > capacity {
+        return Err(not_found().to_string());
+    }
+
+    let message = read_bytes(length);
+    if crc::crc32::checksum_ieee(&message) != checksum {
+        return Err(not_found().to_string());
+    }
+    Ok(message)
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: [u8; 32] = [7; 32];
+
+    fn header(color_type: ColorType, bit_depth: u8) -> ImageHeader {
+        ImageHeader {
+            width: 16,
+            height: 8,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        }
+    }
+
+    fn raw_pixels(header: &ImageHeader) -> Vec<u8> {
+        let len = header.width as usize * header.height as usize * header.bits_per_pixel() / 8;
+        (0..len).map(|i| (i * 37 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_embed_extract_round_trip() {
+        let header = header(ColorType::Rgb, 8);
+        let original = raw_pixels(&header);
+        let mut raw = original.clone();
+        let message = b"Your secret message";
+
+        embed(&header, &mut raw, &Layout::default(), &SEED, message).unwrap();
+
+        assert_ne!(raw, original);
+        assert!(raw
+            .iter()
+            .zip(original.iter())
+            .all(|(a, b)| a & !1 == b & !1));
+        assert_eq!(
+            extract(&header, &raw, &Layout::default(), &SEED).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn test_extract_with_wrong_seed_or_layout() {
+        let header = header(ColorType::Rgba, 8);
+        let mut raw = raw_pixels(&header);
+        embed(&header, &mut raw, &Layout::default(), &SEED, b"secret").unwrap();
+
+        assert!(extract(&header, &raw, &Layout::default(), &[8; 32]).is_err());
+
+        let alpha = Layout {
+            channels: vec![Channel::Alpha],
+            bit_planes: vec![0],
+        };
+        assert!(extract(&header, &raw, &alpha, &SEED).is_err());
+    }
+
+    #[test]
+    fn test_sixteen_bit_planes() {
+        let header = header(ColorType::GrayscaleAlpha, 16);
+        let original = raw_pixels(&header);
+        let mut raw = original.clone();
+        let layout = Layout {
+            channels: vec![Channel::Red, Channel::Alpha],
+            bit_planes: vec![0, 1, 8],
+        };
+
+        embed(&header, &mut raw, &layout, &SEED, b"sixteen bits").unwrap();
+
+        // Bits 0, 1 and 8 live in the low two bits of the second byte and the low bit
+        // of the first byte of each sample.
+        for (index, (a, b)) in raw.iter().zip(original.iter()).enumerate() {
+            let mask = if index % 2 == 0 { !1 } else { !3 };
+            assert_eq!(a & mask, b & mask);
+        }
+        assert_eq!(
+            extract(&header, &raw, &layout, &SEED).unwrap(),
+            b"sixteen bits"
+        );
+    }
+
+    #[test]
+    fn test_capacity() {
+        // 128 pixels * 3 channels * 1 plane = 384 bits = 48 bytes, less the prefix.
+        let header = header(ColorType::Rgb, 8);
+        assert_eq!(capacity(&header, &Layout::default()).unwrap(), 40);
+
+        let mut raw = raw_pixels(&header);
+        assert!(embed(&header, &mut raw, &Layout::default(), &SEED, &[0; 40]).is_ok());
+        assert!(
+            embed(&header, &mut raw, &Layout::default(), &SEED, &[0; 41])
+                .unwrap_err()
+                .contains("only has room for 40")
+        );
+    }
+
+    #[test]
+    fn test_rejects_unsupported_layouts() {
+        let layout = Layout::default();
+        assert!(capacity(&header(ColorType::Indexed, 8), &layout).is_err());
+        assert!(capacity(&header(ColorType::Grayscale, 4), &layout).is_err());
+
+        let alpha = Layout {
+            channels: vec![Channel::Alpha],
+            bit_planes: vec![0],
+        };
+        assert!(capacity(&header(ColorType::Rgb, 8), &alpha).is_err());
+
+        let high_plane = Layout {
+            channels: vec![Channel::Red],
+            bit_planes: vec![8],
+        };
+        assert!(capacity(&header(ColorType::Rgb, 8), &high_plane).is_err());
+    }
+
+    #[test]
+    fn test_order_is_a_permutation() {
+        let mut order: Vec<usize> = Order::new(&SEED, 100).collect();
+        assert_ne!(order, (0..100).collect::<Vec<_>>());
+
+        order.sort_unstable();
+        assert_eq!(order, (0..100).collect::<Vec<_>>());
+    }
+}
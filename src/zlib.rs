@@ -0,0 +1,177 @@
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use std::io::{Read, Write};
+
+/// Inflates a complete zlib stream, as stored across a PNG's IDAT chunks. Fails if
+/// the output would exceed `limit` bytes.
+pub fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    Ok(decompress_prefix(data, limit)?.0)
+}
+
+/// Inflates the zlib stream at the start of `data`, returning the output and the
+/// number of input bytes the stream used. Anything after the end of the stream is
+/// ignored, the caller can compare the count with `data.len()`.
+pub fn decompress_prefix(data: &[u8], limit: usize) -> Result<(Vec<u8>, usize), String> {
+    let mut decoder = ZlibDecoder::new(data);
+    let output = read_limited(&mut decoder, limit)?;
+    Ok((output, decoder.total_in() as usize))
+}
+
+/// Inflates a zlib stream split across `parts`, such as the data of each IDAT chunk,
+/// without first joining them into one buffer.
+pub fn decompress_parts(parts: &[&[u8]], limit: usize) -> Result<Vec<u8>, String> {
+    read_limited(decoder(parts), limit)
+}
+
+/// A reader inflating the zlib stream split across `parts` as it is read, for callers
+/// that process the output in pieces.
+pub fn decoder<'a>(parts: &'a [&'a [u8]]) -> impl Read + 'a {
+    ZlibDecoder::new(Parts { parts, offset: 0 })
+}
+
+/// Inflates as much of a damaged or truncated zlib stream as possible, stopping at
+/// `limit` bytes. Returns the recovered output and, if the stream did not end
+/// cleanly, the reason.
+pub fn decompress_partial(data: &[u8], limit: usize) -> (Vec<u8>, Option<String>) {
+    let mut output = Vec::new();
+    let mut decoder = ZlibDecoder::new(data);
+    let mut buffer = [0; 8192];
+    loop {
+        match decoder.read(&mut buffer) {
+            Ok(0) => return (output, None),
+            Ok(read) if output.len() + read > limit => {
+                output.extend_from_slice(&buffer[..limit - output.len()]);
+                return (output, Some(limit_error(limit)));
+            }
+            Ok(read) => output.extend_from_slice(&buffer[..read]),
+            Err(e) => return (output, Some(format!("invalid zlib stream: {}", e))),
+        }
+    }
+}
+
+fn read_limited<R: Read>(reader: R, limit: usize) -> Result<Vec<u8>, String> {
+    let mut output = Vec::new();
+    reader
+        .take((limit as u64).saturating_add(1))
+        .read_to_end(&mut output)
+        .map_err(|e| format!("invalid zlib stream: {}", e))?;
+    if output.len() > limit {
+        return Err(limit_error(limit));
+    }
+    Ok(output)
+}
+
+/// Reads a list of slices one after the other.
+struct Parts<'a> {
+    parts: &'a [&'a [u8]],
+    /// Bytes already read from `parts[0]`.
+    offset: usize,
+}
+
+impl Read for Parts<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while let Some((first, rest)) = self.parts.split_first() {
+            let remaining = &first[self.offset..];
+            if remaining.is_empty() {
+                self.parts = rest;
+                self.offset = 0;
+                continue;
+            }
+            let read = remaining.len().min(buf.len());
+            buf[..read].copy_from_slice(&remaining[..read]);
+            self.offset += read;
+            return Ok(read);
+        }
+        Ok(0)
+    }
+}
+
+fn limit_error(limit: usize) -> String {
+    format!("zlib stream inflates to more than {} bytes", limit)
+}
+
+/// Deflates `data` into a zlib stream using the best compression level.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .expect("writing to a Vec cannot fail")
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"This is where your secret message will be!".repeat(10);
+        let compressed = compress(&data);
+
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress(&compressed, usize::MAX).unwrap(), data);
+    }
+
+    #[test]
+    fn test_decompress_prefix_reports_trailing_bytes() {
+        let data = b"This is where your secret message will be!";
+        let compressed = compress(data);
+        let padded = [compressed.as_slice(), b"hidden"].concat();
+
+        let (output, used) = decompress_prefix(&padded, usize::MAX).unwrap();
+
+        assert_eq!(output, data);
+        assert_eq!(used, compressed.len());
+    }
+
+    #[test]
+    fn test_decompress_partial() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let compressed = compress(&data);
+
+        let (output, error) = decompress_partial(&compressed, usize::MAX);
+        assert_eq!(output, data);
+        assert!(error.is_none());
+
+        let (output, error) = decompress_partial(&compressed[..compressed.len() / 2], usize::MAX);
+        assert!(!output.is_empty() && output.len() < data.len());
+        assert_eq!(output, data[..output.len()]);
+        assert!(error.is_some());
+    }
+
+    #[test]
+    fn test_decompress_invalid() {
+        assert!(decompress(b"not a zlib stream", usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_decompress_limit() {
+        let data = vec![0; 100_000];
+        let compressed = compress(&data);
+
+        assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+        assert!(decompress(&compressed, data.len() - 1)
+            .unwrap_err()
+            .contains("more than"));
+
+        let (output, error) = decompress_partial(&compressed, 1000);
+        assert_eq!(output.len(), 1000);
+        assert!(error.is_some());
+    }
+
+    #[test]
+    fn test_decompress_parts() {
+        let data = b"This is where your secret message will be!".repeat(100);
+        let compressed = compress(&data);
+        let mut parts: Vec<&[u8]> = vec![&compressed[..1], &[]];
+        parts.extend(compressed[1..].chunks(7));
+
+        assert_eq!(decompress_parts(&parts, usize::MAX).unwrap(), data);
+        assert!(decompress_parts(&parts[..parts.len() / 2], usize::MAX).is_err());
+        assert!(decompress_parts(&parts, 100).is_err());
+    }
+}
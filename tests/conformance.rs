@@ -0,0 +1,495 @@
+//! Conformance suite modeled on PngSuite. `tests/conformance` holds one image for every
+//! color type, bit depth, interlace method and filter type, each with its expected
+//! pixels, plus images the decoder must reject.
+//!
+//! Valid images are named `{kind}{depth}-{n|i}-{filter}.png`, where `n` is
+//! non-interlaced and `i` is Adam7, and `{name}.rgba16` holds the expected pixels as
+//! big-endian 16-bit RGBA. Invalid images start with `x-`. The expected pixels are
+//! computed from the generated samples, not by the decoder. To regenerate the files
+//! after changing the generator, run
+//!
+//!     cargo test --test conformance -- --ignored
+
+use png_rs::chunk::Chunk;
+use png_rs::chunk_type::ChunkType;
+use png_rs::ihdr::{ColorType, ImageHeader};
+use png_rs::image;
+use png_rs::png::Png;
+use png_rs::zlib;
+
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Odd sizes, so that several Adam7 passes have partial rows and columns.
+const WIDTH: u32 = 13;
+const HEIGHT: u32 = 11;
+
+const FILTERS: [&str; 5] = ["none", "sub", "up", "average", "paeth"];
+
+/// Invalid images and a part of the error each must fail with.
+const INVALID: [(&str, &str); 12] = [
+    ("x-bad-crc", "crc"),
+    ("x-missing-ihdr", "missing IHDR"),
+    ("x-zero-width", "invalid image dimensions"),
+    ("x-zero-height", "invalid image dimensions"),
+    ("x-bad-bit-depth", "bit depth 4 is not allowed"),
+    ("x-bad-color-type", "invalid color type"),
+    ("x-bad-interlace", "unknown interlace method"),
+    ("x-bad-filter-type", "invalid filter type"),
+    ("x-missing-plte", "without PLTE"),
+    ("x-bad-palette-index", "palette index"),
+    ("x-truncated-idat", "zlib"),
+    ("x-huge-interlaced", "exceed the limit"),
+];
+
+fn suite_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/conformance")
+}
+
+/// A generated test image.
+struct Case {
+    name: String,
+    png: Vec<u8>,
+    expected: Vec<[u16; 4]>,
+}
+
+/// Deterministic sample in the range of `bit_depth`, spread over the whole range so
+/// that every filter type sees large differences.
+fn sample(x: u32, y: u32, channel: u32, bit_depth: u8) -> u16 {
+    let mut hash = x.wrapping_mul(0x9e37_79b1)
+        ^ y.wrapping_mul(0x85eb_ca77)
+        ^ (channel + 1).wrapping_mul(0xc2b2_ae3d);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x2c1b_3c6d);
+    hash ^= hash >> 12;
+    (hash & ((1u32 << bit_depth) - 1)) as u16
+}
+
+/// Scales a sample to 16 bits, e.g. 2-bit 3 and 8-bit 255 both become 65535.
+fn scale(value: u16, bit_depth: u8) -> u16 {
+    value * (65535 / ((1u32 << bit_depth) - 1)) as u16
+}
+
+fn palette(bit_depth: u8) -> Vec<u8> {
+    (0..1u32 << bit_depth)
+        .flat_map(|index| {
+            [
+                (index * 37) as u8,
+                (index * 91 + 7) as u8,
+                255 - index as u8,
+            ]
+        })
+        .collect()
+}
+
+/// Alpha for the first half of the palette, the rest stays opaque.
+fn palette_alpha(bit_depth: u8) -> Vec<u8> {
+    (0..(1u32 << bit_depth) / 2)
+        .map(|index| (index * 53 + 11) as u8)
+        .collect()
+}
+
+/// Packs rows of samples into scanlines, zero-padding the last byte of each row.
+fn pack(rows: &[Vec<u16>], bit_depth: u8) -> Vec<u8> {
+    let mut raw = Vec::new();
+    for row in rows {
+        match bit_depth {
+            16 => raw.extend(row.iter().flat_map(|value| value.to_be_bytes())),
+            8 => raw.extend(row.iter().map(|&value| value as u8)),
+            bits => {
+                let per_byte = 8 / bits as usize;
+                for group in row.chunks(per_byte) {
+                    let mut byte = 0u8;
+                    for (i, &value) in group.iter().enumerate() {
+                        byte |= (value as u8) << (8 - bits as usize * (i + 1));
+                    }
+                    raw.push(byte);
+                }
+            }
+        }
+    }
+    raw
+}
+
+fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+    Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+}
+
+fn kind_name(color_type: ColorType) -> &'static str {
+    match color_type {
+        ColorType::Grayscale => "gray",
+        ColorType::Rgb => "rgb",
+        ColorType::Indexed => "pal",
+        ColorType::GrayscaleAlpha => "graya",
+        ColorType::Rgba => "rgba",
+    }
+}
+
+/// The samples of every pixel and the pixels they should decode to.
+fn pixels(header: &ImageHeader) -> (Vec<Vec<u16>>, Vec<[u16; 4]>) {
+    let depth = header.bit_depth;
+    let channels = header.color_type.channels() as u32;
+    let (palette, alpha) = match header.color_type {
+        ColorType::Indexed => (palette(depth), palette_alpha(depth)),
+        _ => (Vec::new(), Vec::new()),
+    };
+
+    let mut rows = Vec::new();
+    let mut expected = Vec::new();
+    for y in 0..header.height {
+        let mut row = Vec::new();
+        for x in 0..header.width {
+            let samples: Vec<u16> = (0..channels).map(|c| sample(x, y, c, depth)).collect();
+            let scaled: Vec<u16> = samples.iter().map(|&value| scale(value, depth)).collect();
+            expected.push(match header.color_type {
+                ColorType::Grayscale => [scaled[0], scaled[0], scaled[0], 65535],
+                ColorType::GrayscaleAlpha => [scaled[0], scaled[0], scaled[0], scaled[1]],
+                ColorType::Rgb => [scaled[0], scaled[1], scaled[2], 65535],
+                ColorType::Rgba => [scaled[0], scaled[1], scaled[2], scaled[3]],
+                ColorType::Indexed => {
+                    let index = samples[0] as usize;
+                    let entry = &palette[index * 3..index * 3 + 3];
+                    let alpha = alpha.get(index).copied().unwrap_or(255);
+                    [entry[0], entry[1], entry[2], alpha].map(|value| value as u16 * 257)
+                }
+            });
+            row.extend(samples);
+        }
+        rows.push(row);
+    }
+    (rows, expected)
+}
+
+fn build(header: ImageHeader, extra: Vec<Chunk>, raw: &[u8], filter: Option<u8>) -> Vec<u8> {
+    let data = image::encode_pixels_with_filter(&header, raw, filter).unwrap();
+    let mut chunks = vec![header.to_chunk()];
+    chunks.extend(extra);
+    chunks.push(chunk("IDAT", data));
+    chunks.push(chunk("IEND", Vec::new()));
+    Png::from_chunks(chunks).as_bytes()
+}
+
+fn valid_cases() -> Vec<Case> {
+    let mut cases = Vec::new();
+    for color_type in [
+        ColorType::Grayscale,
+        ColorType::Rgb,
+        ColorType::Indexed,
+        ColorType::GrayscaleAlpha,
+        ColorType::Rgba,
+    ] {
+        for &bit_depth in color_type.allowed_bit_depths() {
+            for interlaced in [false, true] {
+                for (filter, filter_name) in FILTERS.iter().enumerate() {
+                    let header = ImageHeader {
+                        width: WIDTH,
+                        height: HEIGHT,
+                        bit_depth,
+                        color_type,
+                        interlaced,
+                    };
+                    let (rows, expected) = pixels(&header);
+                    let extra = match color_type {
+                        ColorType::Indexed => vec![
+                            chunk("PLTE", palette(bit_depth)),
+                            chunk("tRNS", palette_alpha(bit_depth)),
+                        ],
+                        _ => Vec::new(),
+                    };
+                    cases.push(Case {
+                        name: format!(
+                            "{}{}-{}-{}",
+                            kind_name(color_type),
+                            bit_depth,
+                            if interlaced { "i" } else { "n" },
+                            filter_name
+                        ),
+                        png: build(header, extra, &pack(&rows, bit_depth), Some(filter as u8)),
+                        expected,
+                    });
+                }
+            }
+        }
+    }
+
+    // Transparent color keys, which turn matching pixels fully transparent.
+    for (color_type, bit_depth) in [(ColorType::Grayscale, 8), (ColorType::Rgb, 16)] {
+        let header = ImageHeader {
+            width: WIDTH,
+            height: HEIGHT,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        };
+        let (rows, mut expected) = pixels(&header);
+        let channels = color_type.channels();
+        let key = &rows[1][channels..channels * 2];
+        for (pixel, samples) in expected
+            .iter_mut()
+            .zip(rows.iter().flat_map(|row| row.chunks(channels)))
+        {
+            if samples == key {
+                pixel[3] = 0;
+            }
+        }
+        let trns = key.iter().flat_map(|value| value.to_be_bytes()).collect();
+        cases.push(Case {
+            name: format!("{}{}-trns", kind_name(color_type), bit_depth),
+            png: build(
+                header,
+                vec![chunk("tRNS", trns)],
+                &pack(&rows, bit_depth),
+                None,
+            ),
+            expected,
+        });
+    }
+
+    cases
+}
+
+fn gray8(width: u32, height: u32) -> ImageHeader {
+    ImageHeader {
+        width,
+        height,
+        bit_depth: 8,
+        color_type: ColorType::Grayscale,
+        interlaced: false,
+    }
+}
+
+/// Builds a file from raw IHDR bytes and an already compressed IDAT stream.
+fn build_raw(ihdr: Vec<u8>, extra: Vec<Chunk>, idat: Vec<u8>) -> Vec<u8> {
+    let mut chunks = vec![chunk("IHDR", ihdr)];
+    chunks.extend(extra);
+    chunks.push(chunk("IDAT", idat));
+    chunks.push(chunk("IEND", Vec::new()));
+    Png::from_chunks(chunks).as_bytes()
+}
+
+fn invalid_case(name: &str) -> Vec<u8> {
+    let header = gray8(4, 4);
+    let raw = vec![128; 16];
+    let idat = image::encode_pixels(&header, &raw).unwrap();
+    let with_ihdr_byte = |index: usize, value: u8| {
+        let mut ihdr = header.to_bytes();
+        ihdr[index] = value;
+        build_raw(ihdr, Vec::new(), idat.clone())
+    };
+
+    match name {
+        "x-bad-crc" => {
+            let mut bytes = build(header, Vec::new(), &raw, None);
+            // The last byte of the IDAT CRC, just before the 12-byte IEND chunk.
+            let crc_end = bytes.len() - 13;
+            bytes[crc_end] ^= 0x01;
+            bytes
+        }
+        "x-missing-ihdr" => {
+            Png::from_chunks(vec![chunk("IDAT", idat), chunk("IEND", Vec::new())]).as_bytes()
+        }
+        "x-zero-width" => build_raw(gray8(0, 4).to_bytes(), Vec::new(), idat),
+        "x-zero-height" => build_raw(gray8(4, 0).to_bytes(), Vec::new(), idat),
+        "x-bad-bit-depth" => {
+            let mut ihdr = header.to_bytes();
+            ihdr[8] = 4;
+            ihdr[9] = ColorType::Rgb as u8;
+            build_raw(ihdr, Vec::new(), idat)
+        }
+        "x-bad-color-type" => with_ihdr_byte(9, 1),
+        "x-bad-interlace" => with_ihdr_byte(12, 2),
+        "x-bad-filter-type" => {
+            let filtered: Vec<u8> = (0..4).flat_map(|_| [5, 1, 2, 3, 4]).collect();
+            build_raw(header.to_bytes(), Vec::new(), zlib::compress(&filtered))
+        }
+        "x-missing-plte" => {
+            let header = ImageHeader {
+                color_type: ColorType::Indexed,
+                ..header
+            };
+            build(header, Vec::new(), &[0; 16], None)
+        }
+        "x-bad-palette-index" => {
+            let header = ImageHeader {
+                bit_depth: 2,
+                color_type: ColorType::Indexed,
+                ..header
+            };
+            // Two palette entries, but the last pixel uses index 3.
+            let plte = chunk("PLTE", vec![0, 0, 0, 255, 255, 255]);
+            build(header, vec![plte], &[0b0001_0000, 0, 0, 0b0000_0011], None)
+        }
+        "x-truncated-idat" => build_raw(
+            header.to_bytes(),
+            Vec::new(),
+            idat[..idat.len() / 2].to_vec(),
+        ),
+        // A 2^20 x 2^20 interlaced RGBA16 header over a tiny IDAT, which used to
+        // make `inspect` allocate 8 TiB.
+        "x-huge-interlaced" => {
+            let header = ImageHeader {
+                width: 1 << 20,
+                height: 1 << 20,
+                bit_depth: 16,
+                color_type: ColorType::Rgba,
+                interlaced: true,
+            };
+            build_raw(header.to_bytes(), Vec::new(), idat)
+        }
+        _ => panic!("unknown invalid case {}", name),
+    }
+}
+
+/// Decodes a file into 16-bit RGBA, the way `Png::pixel_hash` sees it.
+fn decode(bytes: &[u8]) -> Result<Vec<[u16; 4]>, String> {
+    let png = Png::try_from(bytes)?;
+    let header = png.image_header()?;
+    let raw = image::decode_pixels(&header, &png.idat_data(), png.limits())?;
+    image::rgba16_pixels(
+        &header,
+        &raw,
+        png.chunk_by_type("PLTE").map(Chunk::data),
+        png.chunk_by_type("tRNS").map(Chunk::data),
+    )
+}
+
+/// Like `decode`, through the row-at-a-time decoder used by the pixel hash.
+fn decode_streaming(bytes: &[u8]) -> Result<Vec<[u16; 4]>, String> {
+    let png = Png::try_from(bytes)?;
+    let parts: Vec<&[u8]> = png.chunks_by_type("IDAT").map(Chunk::data).collect();
+    let mut pixels = Vec::new();
+    image::for_each_rgba16_row(
+        &png.image_header()?,
+        &parts,
+        png.chunk_by_type("PLTE").map(Chunk::data),
+        png.chunk_by_type("tRNS").map(Chunk::data),
+        png.limits(),
+        |row| pixels.extend_from_slice(row),
+    )?;
+    Ok(pixels)
+}
+
+fn to_bytes(pixels: &[[u16; 4]]) -> Vec<u8> {
+    pixels
+        .iter()
+        .flat_map(|rgba| rgba.iter().flat_map(|value| value.to_be_bytes()))
+        .collect()
+}
+
+fn from_bytes(bytes: &[u8]) -> Vec<[u16; 4]> {
+    bytes
+        .chunks(8)
+        .map(|rgba| [0, 2, 4, 6].map(|i| u16::from_be_bytes([rgba[i], rgba[i + 1]])))
+        .collect()
+}
+
+#[test]
+#[ignore = "rewrites tests/conformance"]
+fn generate_suite() {
+    let dir = suite_dir();
+    fs::create_dir_all(&dir).unwrap();
+    for case in valid_cases() {
+        fs::write(dir.join(format!("{}.png", case.name)), &case.png).unwrap();
+        fs::write(
+            dir.join(format!("{}.rgba16", case.name)),
+            to_bytes(&case.expected),
+        )
+        .unwrap();
+    }
+    for (name, _) in INVALID {
+        fs::write(dir.join(format!("{}.png", name)), invalid_case(name)).unwrap();
+    }
+}
+
+#[test]
+fn test_suite_is_complete() {
+    let mut expected: Vec<String> = valid_cases()
+        .iter()
+        .flat_map(|case| {
+            [
+                format!("{}.png", case.name),
+                format!("{}.rgba16", case.name),
+            ]
+        })
+        .chain(INVALID.iter().map(|(name, _)| format!("{}.png", name)))
+        .collect();
+    let mut found: Vec<String> = fs::read_dir(suite_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    expected.sort();
+    found.sort();
+
+    // 15 color type and bit depth pairs, 2 interlace methods and 5 filters.
+    assert_eq!(valid_cases().len(), 15 * 2 * 5 + 2);
+    assert_eq!(found, expected);
+}
+
+#[test]
+fn test_valid_images_decode_to_expected_pixels() {
+    for case in valid_cases() {
+        let png = fs::read(suite_dir().join(format!("{}.png", case.name))).unwrap();
+        let expected = fs::read(suite_dir().join(format!("{}.rgba16", case.name))).unwrap();
+        assert_eq!(
+            from_bytes(&expected),
+            case.expected,
+            "{}: stale expected pixels",
+            case.name
+        );
+
+        let pixels = decode(&png).unwrap_or_else(|e| panic!("{}: {}", case.name, e));
+        assert_eq!(pixels, case.expected, "{}", case.name);
+        let streamed = decode_streaming(&png).unwrap_or_else(|e| panic!("{}: {}", case.name, e));
+        assert_eq!(streamed, case.expected, "{}: streaming decoder", case.name);
+
+        // The 8-bit decoder keeps the high byte of every sample.
+        let rgba = Png::try_from(png.as_slice())
+            .unwrap()
+            .decode_rgba()
+            .unwrap();
+        let high_bytes: Vec<u8> = pixels
+            .iter()
+            .flat_map(|rgba| rgba.map(|value| (value >> 8) as u8))
+            .collect();
+        assert_eq!(rgba.pixels, high_bytes, "{}", case.name);
+    }
+}
+
+#[test]
+fn test_filter_and_interlace_are_as_named() {
+    for case in valid_cases()
+        .iter()
+        .filter(|case| !case.name.ends_with("-trns"))
+    {
+        let png = Png::try_from(case.png.as_slice()).unwrap();
+        let header = png.image_header().unwrap();
+        let filtered = zlib::decompress(&png.idat_data(), usize::MAX).unwrap();
+        let filter = FILTERS
+            .iter()
+            .position(|name| case.name.ends_with(name))
+            .unwrap();
+
+        assert_eq!(
+            header.interlaced,
+            case.name.contains("-i-"),
+            "{}",
+            case.name
+        );
+        assert_eq!(filtered[0] as usize, filter, "{}", case.name);
+    }
+}
+
+#[test]
+fn test_invalid_images_are_rejected() {
+    for (name, error) in INVALID {
+        let bytes = fs::read(suite_dir().join(format!("{}.png", name))).unwrap();
+
+        for decoder in [decode, decode_streaming] {
+            match decoder(&bytes) {
+                Ok(_) => panic!("{} decoded successfully", name),
+                Err(e) => assert!(e.contains(error), "{}: unexpected error {:?}", name, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Checks that hashing an image needs memory proportional to its width, not its
+//! height. This is its own test binary because it replaces the global allocator to
+//! track the peak number of bytes allocated.
+
+use png_rs::chunk::Chunk;
+use png_rs::chunk_type::ChunkType;
+use png_rs::ihdr::{ColorType, ImageHeader};
+use png_rs::image;
+use png_rs::png::Png;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct PeakAllocator;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        PEAK.fetch_max(current, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator;
+
+fn image_png(height: u32, interlaced: bool) -> Png {
+    let header = ImageHeader {
+        width: 64,
+        height,
+        bit_depth: 8,
+        color_type: ColorType::Rgb,
+        interlaced,
+    };
+    let raw: Vec<u8> = (0..64 * 3 * height as usize)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    Png::from_chunks(vec![
+        header.to_chunk(),
+        Chunk::new(
+            ChunkType::from_str("IDAT").unwrap(),
+            image::encode_pixels(&header, &raw).unwrap(),
+        ),
+        Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+    ])
+}
+
+/// Bytes allocated on top of what was already live while hashing `png`.
+fn peak_while_hashing(png: &Png) -> usize {
+    let before = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(before, Ordering::SeqCst);
+    png.pixel_hash().unwrap();
+    PEAK.load(Ordering::SeqCst) - before
+}
+
+#[test]
+fn test_pixel_hash_memory_does_not_grow_with_height() {
+    for interlaced in [false, true] {
+        let short = peak_while_hashing(&image_png(100, interlaced));
+        let tall = peak_while_hashing(&image_png(5_000, interlaced));
+
+        // The tall image decodes to 2.5 MB of RGBA16 pixels, the short one to 50 KB.
+        assert!(
+            tall <= short + 16 * 1024,
+            "interlaced: {}, peak {} bytes for 100 rows but {} for 5000",
+            interlaced,
+            short,
+            tall
+        );
+    }
+}